@@ -0,0 +1,237 @@
+use std::path::Path;
+
+use glam::Vec3;
+
+use crate::importer::{ Importer, Imported, ImportError, parse_number };
+use crate::exporter::{ Exporter, ExportOptions };
+use crate::aabb::AABB;
+use crate::camera::Camera;
+use crate::scene::Scene;
+use crate::integrator::PathTracer;
+use crate::{ DEFAULT_WIDTH, DEFAULT_HEIGHT };
+
+pub const USAGE: &str = "\
+Usage: rustpt render <file.scene|mesh.obj|mesh.gltf|mesh.glb|mesh.stl|mesh.ply> [options]
+
+Options override what a .scene file sets:
+    --spp <n>          Samples per pixel (default 64)
+    --out <file.ppm>   Output image (default render.ppm)
+    --width <px>       Image width (default 512)
+    --height <px>      Image height (default 288)
+    --max-depth <n>    Maximum path length (default 8)
+    --fov <degrees>    Vertical field of view (default 90, 180 and above render as fisheye)
+    --ortho <size>     Orthographic projection showing <size> world units vertically
+    --aperture <r>     Lens radius for depth of field (default 0, pinhole)
+    --focus <d>        Focus distance (default: center of the mesh)
+    --thumbnail <file> Also write a quarter resolution preview before the final render
+    --lenient          Skip malformed OBJ lines and broken glTF triangles instead of failing
+    --weld             Merge duplicate STL vertices and smooth their normals
+
+Usage: rustpt convert <input> <output.obj|output.ply> [options]
+
+Options:
+    --lenient          Skip malformed OBJ lines and broken glTF triangles instead of failing
+    --weld             Merge duplicate STL vertices and smooth their normals
+    --local            Keep vertices relative to each object's origin
+    --no-uvs           Don't write texture coordinates
+    --no-normals       Don't write vertex normals";
+
+pub struct RenderArgs {
+    pub scene: String,
+    pub spp: Option<u32>,
+    pub out: String,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub max_depth: Option<u32>,
+    pub thumbnail: Option<String>,
+    pub fov: Option<f64>,
+    pub ortho_size: Option<f32>,
+    pub aperture: Option<f32>,
+    pub focus_distance: Option<f32>,
+    pub lenient: bool,
+    pub weld: bool,
+}
+
+impl RenderArgs {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut scene = None;
+        let mut result = RenderArgs {
+            scene: String::new(),
+            spp: None,
+            out: "render.ppm".to_string(),
+            width: None,
+            height: None,
+            max_depth: None,
+            thumbnail: None,
+            fov: None,
+            ortho_size: None,
+            aperture: None,
+            focus_distance: None,
+            lenient: false,
+            weld: false,
+        };
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") {
+                if scene.replace(arg.clone()).is_some() {
+                    return Err(format!("Unexpected argument '{}'", arg));
+                }
+                continue;
+            }
+            if arg == "--lenient" {
+                result.lenient = true;
+                continue;
+            }
+            if arg == "--weld" {
+                result.weld = true;
+                continue;
+            }
+            let value = iter.next().ok_or(format!("Missing value for {}", arg))?;
+            match arg.as_str() {
+                "--spp" => result.spp = Some(parse_option(arg, value)?),
+                "--out" => result.out = value.clone(),
+                "--width" => result.width = Some(parse_option(arg, value)?),
+                "--height" => result.height = Some(parse_option(arg, value)?),
+                "--max-depth" => result.max_depth = Some(parse_option(arg, value)?),
+                "--thumbnail" => result.thumbnail = Some(value.clone()),
+                "--fov" => result.fov = Some(parse_option(arg, value)?),
+                "--ortho" => result.ortho_size = Some(parse_option(arg, value)?),
+                "--aperture" => result.aperture = Some(parse_option(arg, value)?),
+                "--focus" => result.focus_distance = Some(parse_option(arg, value)?),
+                _ => return Err(format!("Unknown option '{}'", arg)),
+            }
+        }
+
+        result.scene = scene.ok_or("Missing scene file")?;
+        if [result.spp.map(|spp| spp as usize), result.width, result.height].contains(&Some(0)) {
+            return Err("--spp, --width and --height must be greater than zero".to_string());
+        }
+        Ok(result)
+    }
+}
+
+pub struct ConvertArgs {
+    pub input: String,
+    pub output: String,
+    pub lenient: bool,
+    pub weld: bool,
+    pub options: ExportOptions,
+}
+
+impl ConvertArgs {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut files = Vec::new();
+        let mut lenient = false;
+        let mut weld = false;
+        let mut options = ExportOptions::new();
+        options.apply_origin = true; // Objects of one file keep their placement when merged
+        for arg in args {
+            match arg.as_str() {
+                "--lenient" => lenient = true,
+                "--weld" => weld = true,
+                "--local" => options.apply_origin = false,
+                "--no-uvs" => options.uvs = false,
+                "--no-normals" => options.normals = false,
+                _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'", arg)),
+                _ => files.push(arg.clone()),
+            }
+        }
+        let [input, output] = <[String; 2]>::try_from(files).map_err(|_| "Expected an input and an output file")?;
+        Ok(ConvertArgs { input, output, lenient, weld, options })
+    }
+}
+
+fn parse_option<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    parse_number(value).map_err(|reason| format!("{} for {}", reason, option))
+}
+
+// Imports a mesh and prints any warnings
+fn load(path: &str, lenient: bool, weld: bool) -> Result<Imported, ImportError> {
+    let imported = Importer::file(path, lenient, weld)?;
+    imported.warnings.iter().for_each(|warning| eprintln!("Warning: {}", warning));
+    Ok(imported)
+}
+
+// Scene files are used as they are. Meshes are framed by a camera looking down -Z unless they bring their own
+fn load_scene(args: &RenderArgs) -> Result<Scene, ImportError> {
+    if Path::new(&args.scene).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("scene")) {
+        return Scene::load(&args.scene);
+    }
+    let imported = load(&args.scene, args.lenient, args.weld)?;
+
+    let bounds = imported.objects.iter().fold(AABB::empty(), |bounds, object| bounds.expand(&AABB {
+        min: object.aabb.min + object.origin,
+        max: object.aabb.max + object.origin,
+    }));
+    let center = bounds.center();
+    let radius = (bounds.max - bounds.min).length() / 2.;
+    let mut camera = imported.camera.unwrap_or_else(|| Camera::new(center + Vec3::Z * radius * 1.5, Vec3::ZERO));
+    // Focused on the center of the mesh, measured from whichever camera is used. Imported cameras looking away from it
+    // keep their own focus
+    let center_depth = (center - camera.origin()).dot(camera.front());
+    if center_depth > 0. {
+        camera.focus_distance = center_depth;
+    }
+    Ok(Scene::new(imported.objects, camera, DEFAULT_WIDTH, DEFAULT_HEIGHT))
+}
+
+// Renders without opening a window and writes the result to args.out
+pub fn render(args: &RenderArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut scene = load_scene(args)?;
+
+    let camera = &mut scene.camera;
+    camera.aperture = args.aperture.unwrap_or(camera.aperture);
+    camera.focus_distance = args.focus_distance.unwrap_or(camera.focus_distance);
+    if let Some(fov) = args.fov {
+        camera.fov = fov;
+    }
+    if let Some(ortho_size) = args.ortho_size {
+        camera.orthographic = true;
+        camera.ortho_size = ortho_size;
+    }
+
+    let mut settings = scene.settings.clone();
+    settings.width = args.width.unwrap_or(settings.width);
+    settings.height = args.height.unwrap_or(settings.height);
+    settings.spp = args.spp.unwrap_or(settings.spp);
+    settings.tracer.max_depth = args.max_depth.unwrap_or(settings.tracer.max_depth);
+
+    if let Some(thumbnail) = &args.thumbnail {
+        scene.resize((settings.width / 4).max(1), (settings.height / 4).max(1));
+        render_passes(&mut scene, &settings.tracer, settings.spp.min(16), thumbnail)?;
+    }
+    scene.resize(settings.width, settings.height);
+    render_passes(&mut scene, &settings.tracer, settings.spp, &args.out)?;
+    Ok(())
+}
+
+// Writes every object of the input to one mesh file, the format is picked by extension
+pub fn convert(args: &ConvertArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let extension = Path::new(&args.output).extension().map(|extension| extension.to_string_lossy().to_lowercase());
+    let export = match extension.as_deref() {
+        Some("obj") => Exporter::obj,
+        Some("ply") => Exporter::ply,
+        _ => return Err(format!("Unsupported output format '{}', use .obj or .ply", args.output).into()),
+    };
+    let objects = load(&args.input, args.lenient, args.weld)?.objects;
+    export(&objects, &args.output, &args.options)?;
+    let triangles: usize = objects.iter().map(|object| object.triangles.len()).sum();
+    println!("Wrote {} objects with {} triangles to {}", objects.len(), triangles, args.output);
+    Ok(())
+}
+
+// Accumulates `spp` frames at the scene's current resolution and saves them
+fn render_passes(scene: &mut Scene, tracer: &PathTracer, spp: u32, out: &str) -> std::io::Result<()> {
+    let now = std::time::Instant::now();
+    for frame in 0..spp {
+        scene.render_frame(tracer, frame as u64);
+        print!("\rRendered {}/{} samples per pixel", frame + 1, spp);
+        std::io::Write::flush(&mut std::io::stdout())?;
+    }
+    println!("\nFinished {}x{} in {:.2} seconds", scene.screen.width(), scene.screen.height(), now.elapsed().as_secs_f64());
+
+    scene.screen.to_texture().save_to_path(out)?;
+    println!("Saved {}", out);
+    Ok(())
+}
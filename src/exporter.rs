@@ -0,0 +1,40 @@
+pub mod obj;
+pub mod ply;
+
+use derive_new::new;
+use glam::Vec3;
+
+use crate::object::Object;
+use crate::polygon::Triangle;
+
+#[derive(new, Clone)]
+pub struct ExportOptions {
+    #[new(value = "false")]
+    pub apply_origin: bool, // Move vertices by Object::origin, otherwise they are written in object space
+    #[new(value = "true")]
+    pub uvs: bool,
+    #[new(value = "true")]
+    pub normals: bool,
+}
+
+pub struct Exporter{}
+
+// Triangles of an object as plain copies, moved to world space when the options ask for it
+fn export_triangles(object: &Object, options: &ExportOptions) -> Vec<Triangle> {
+    let offset = if options.apply_origin { object.origin } else { Vec3::ZERO };
+    object.triangles.iter().map(|triangle| {
+        let mut triangle = triangle.read().unwrap().clone();
+        triangle.vertices.iter_mut().for_each(|vertex| vertex.pos += offset);
+        triangle
+    }).collect()
+}
+
+// Inverse of the importers' srgb_to_linear, colors are written the way they were read
+fn linear_to_srgb(color: Vec3) -> Vec3 {
+    color.max(Vec3::ZERO).powf(1. / 2.2)
+}
+
+// Exact bit patterns, so only identical values are merged
+fn vec3_key(value: Vec3) -> [u32; 3] {
+    value.to_array().map(f32::to_bits)
+}
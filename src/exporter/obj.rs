@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{BufWriter, Write};
+
+use glam::Vec3;
+
+use crate::object::Object;
+use super::{ Exporter, ExportOptions, export_triangles, linear_to_srgb, vec3_key };
+
+impl Exporter {
+    // Every named object becomes an `o` group. Shared positions, uvs and normals are written once
+    pub fn obj(objects: &[Object], file_path: &str, options: &ExportOptions) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(file_path)?);
+        writeln!(file, "# Exported by RustPT")?;
+
+        // OBJ indices are global for the whole file
+        let mut positions = HashMap::new();
+        let mut uvs = HashMap::new();
+        let mut normals = HashMap::new();
+        for object in objects {
+            let triangles = export_triangles(object, options);
+            if !object.name.is_empty() {
+                writeln!(file, "o {}", object.name)?;
+            }
+            // White is what the importers assume without colors, so it doesn't need writing
+            let colored = triangles.iter().flat_map(|triangle| &triangle.vertices).any(|vertex| vertex.color != Vec3::ONE);
+
+            for triangle in &triangles {
+                let mut corners = Vec::with_capacity(3);
+                for vertex in &triangle.vertices {
+                    let position = index_of(&mut positions, (vec3_key(vertex.pos), vec3_key(vertex.color)), || {
+                        let color = linear_to_srgb(vertex.color);
+                        match colored {
+                            true => writeln!(file, "v {} {} {} {} {} {}", vertex.pos.x, vertex.pos.y, vertex.pos.z, color.x, color.y, color.z),
+                            false => writeln!(file, "v {} {} {}", vertex.pos.x, vertex.pos.y, vertex.pos.z),
+                        }
+                    })?;
+                    let uv = match options.uvs {
+                        true => Some(index_of(&mut uvs, vertex.uv.to_array().map(f32::to_bits), || writeln!(file, "vt {} {}", vertex.uv.x, vertex.uv.y))?),
+                        false => None,
+                    };
+                    let normal = match vertex.normal.filter(|_| options.normals) {
+                        Some(normal) => Some(index_of(&mut normals, vec3_key(normal), || writeln!(file, "vn {} {} {}", normal.x, normal.y, normal.z))?),
+                        None => None,
+                    };
+                    corners.push(match (uv, normal) {
+                        (Some(uv), Some(normal)) => format!("{}/{}/{}", position, uv, normal),
+                        (Some(uv), None) => format!("{}/{}", position, uv),
+                        (None, Some(normal)) => format!("{}//{}", position, normal),
+                        (None, None) => position.to_string(),
+                    });
+                }
+                writeln!(file, "f {}", corners.join(" "))?;
+            }
+        }
+        file.flush()
+    }
+}
+
+// 1 based index of an element, `write` is only called the first time the key is seen
+fn index_of<K: Hash + Eq>(indices: &mut HashMap<K, usize>, key: K, write: impl FnOnce() -> std::io::Result<()>) -> std::io::Result<usize> {
+    if let Some(index) = indices.get(&key) {
+        return Ok(*index);
+    }
+    write()?;
+    let index = indices.len() + 1;
+    indices.insert(key, index);
+    Ok(index)
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use glam::Vec3;
+
+use crate::object::Object;
+use crate::polygon::Vertex;
+use super::{ Exporter, ExportOptions, export_triangles, linear_to_srgb, vec3_key };
+
+impl Exporter {
+    // Binary little endian PLY. PLY has no groups, all objects are merged into one mesh
+    pub fn ply(objects: &[Object], file_path: &str, options: &ExportOptions) -> std::io::Result<()> {
+        let triangles: Vec<_> = objects.iter().flat_map(|object| export_triangles(object, options)).collect();
+
+        // Vertices are shared when every written property matches
+        let mut vertices: Vec<&Vertex> = Vec::new();
+        let mut indices: HashMap<Vec<u32>, u32> = HashMap::new();
+        let faces: Vec<[u32; 3]> = triangles.iter().map(|triangle| triangle.vertices.each_ref().map(|vertex| {
+            let mut key = vec3_key(vertex.pos).to_vec();
+            key.extend(vec3_key(vertex.color));
+            if options.uvs {
+                key.extend(vertex.uv.to_array().map(f32::to_bits));
+            }
+            if options.normals {
+                key.extend(vec3_key(vertex.normal.unwrap_or(Vec3::ZERO)));
+            }
+            *indices.entry(key).or_insert_with(|| {
+                vertices.push(vertex);
+                vertices.len() as u32 - 1
+            })
+        })).collect();
+        let normals = options.normals && vertices.iter().any(|vertex| vertex.normal.is_some());
+        let colored = vertices.iter().any(|vertex| vertex.color != Vec3::ONE);
+
+        let mut file = BufWriter::new(File::create(file_path)?);
+        writeln!(file, "ply\nformat binary_little_endian 1.0\ncomment Exported by RustPT")?;
+        writeln!(file, "element vertex {}\nproperty float x\nproperty float y\nproperty float z", vertices.len())?;
+        if normals {
+            // Vertices without a normal get a zero one, which the importer reads back as none
+            writeln!(file, "property float nx\nproperty float ny\nproperty float nz")?;
+        }
+        if options.uvs {
+            writeln!(file, "property float u\nproperty float v")?;
+        }
+        if colored {
+            writeln!(file, "property uchar red\nproperty uchar green\nproperty uchar blue")?;
+        }
+        writeln!(file, "element face {}\nproperty list uchar uint vertex_indices\nend_header", faces.len())?;
+
+        for vertex in vertices {
+            let mut floats = vertex.pos.to_array().to_vec();
+            if normals {
+                floats.extend(vertex.normal.unwrap_or(Vec3::ZERO).to_array());
+            }
+            if options.uvs {
+                floats.extend(vertex.uv.to_array());
+            }
+            for value in floats {
+                file.write_all(&value.to_le_bytes())?;
+            }
+            if colored {
+                let color = (linear_to_srgb(vertex.color).min(Vec3::ONE) * 255.).round();
+                file.write_all(&[color.x as u8, color.y as u8, color.z as u8])?;
+            }
+        }
+        for face in faces {
+            file.write_all(&[3])?;
+            for index in face {
+                file.write_all(&index.to_le_bytes())?;
+            }
+        }
+        file.flush()
+    }
+}
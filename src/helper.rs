@@ -6,8 +6,21 @@ pub mod camera;
 pub mod scene;
 pub mod texture;
 pub mod aabb;
+pub mod sampler;
+pub mod integrator;
+pub mod material;
+pub mod watcher;
+
+use glam::Vec3;
 
 #[inline]
 pub fn ARGB4_to_ARGBu32(a: u8, r: u8, g: u8, b: u8) -> u32 {
     ((a as u32) << 24) + ((r as u32) << 16) + ((g as u32) << 8) + b as u32
 }
+
+// Clamps linear radiance and applies display gamma
+#[inline]
+pub fn linear_to_ARGBu32(color: Vec3) -> u32 {
+    let srgb = color.clamp(Vec3::ZERO, Vec3::ONE).powf(1./2.2) * 255.;
+    ARGB4_to_ARGBu32(0xFF, srgb.x as u8, srgb.y as u8, srgb.z as u8)
+}
@@ -1,11 +1,12 @@
 use glam::Vec3;
 
 pub mod bvh;
+pub mod tlas;
 
 use crate::polygon::Vertex;
-use crate::polygon::Triangle;
+use crate::ray::Ray;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct AABB {
     pub min: Vec3,
     pub max: Vec3,
@@ -19,44 +20,50 @@ impl AABB {
         }
     }
 
-    pub fn new(vertices: Vec<&Vertex>) -> Self {
-        let mut min = Vec3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
-        let mut max = Vec3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
-
-        vertices.iter().for_each(|vertex| {
-            min = min.min(vertex.pos);
-        });
+    pub fn expand(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max)
+        }
+    }
 
-        vertices.iter().for_each(|vertex| {
-            max = max.max(vertex.pos);
-        });
+    pub fn include_vertex(&self, vertex: Vertex) -> Self {
+        Self {
+            min: self.min.min(vertex.pos),
+            max: self.max.max(vertex.pos),
+        }
+    }
 
+    pub fn include_point(&self, point: Vec3) -> Self {
         Self {
-            min,
-            max,
+            min: self.min.min(point),
+            max: self.max.max(point),
         }
     }
 
-    pub fn new_from_tri(triangles: &Vec<Triangle>) -> Self {
-        let vertices: Vec<&Vertex> = triangles.iter().flat_map(|tri| {
-            tri.vertices.iter().map(|vertex| {
-                vertex
-            })
-        }).collect();
-        Self::new(vertices)
+    // Zero for empty boxes
+    pub fn surface_area(&self) -> f32 {
+        let size = (self.max - self.min).max(Vec3::ZERO);
+        2. * (size.x * size.y + size.y * size.z + size.z * size.x)
     }
 
-    pub fn expand(&self, other: &Self) -> Self {
+    pub fn translate(&self, offset: Vec3) -> Self {
         Self {
-            min: self.min.min(other.min),
-            max: self.max.max(other.max)
+            min: self.min + offset,
+            max: self.max + offset,
         }
     }
 
-    pub fn include_vertex(&self, vertex: Vertex) -> Self {
-        Self {
-            min: self.min.min(vertex.pos),
-            max: self.max.max(vertex.pos),
+    // Distance along the ray where it enters the box, 0 when it starts inside. inv_direction is 1 / ray.direction
+    pub fn ray_distance(&self, ray: &Ray, inv_direction: Vec3) -> Option<f32> {
+        let t0 = (self.min - ray.origin) * inv_direction;
+        let t1 = (self.max - ray.origin) * inv_direction;
+        let tmin = t0.min(t1).max_element();
+        let tmax = t0.max(t1).min_element();
+        if tmin <= tmax && tmax >= 0. {
+            Some(tmin.max(0.))
+        } else {
+            None
         }
     }
 
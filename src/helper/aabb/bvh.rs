@@ -1,86 +1,357 @@
+use std::fmt;
 use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use glam::Vec3;
+use rayon::prelude::*;
 
 use crate::{aabb::AABB, helper::polygon::Triangle,};
+use crate::ray::{ Ray, IntersectionResult };
+
+// Binned SAH: centroids are sorted into this many buckets per axis and only bucket borders are tried as splits
+const SAH_BINS: usize = 12;
+// Relative costs of visiting a node and intersecting a triangle
+const TRAVERSAL_COST: f32 = 1.;
+const INTERSECTION_COST: f32 = 1.;
+// Bigger sets are always split, smaller ones become a leaf when SAH says splitting doesn't pay off
+const MAX_LEAF_TRIANGLES: usize = 8;
+// Smaller sets are built on the current thread, splitting them up costs more than it saves
+const PARALLEL_TRIANGLES: usize = 4096;
+// Traversal uses a fixed size stack, deeper sets become a leaf whatever their size. Only degenerate meshes get there
+pub const MAX_DEPTH: usize = 64;
 
+// Nodes are 32 bytes so two of them share a cache line
 #[derive(Debug, Clone)]
-pub enum BVHNode {
-    Leaf(Arc<RwLock<Triangle>>),
-    Node(Box<BVH>),
+pub struct BVHNode {
+    pub aabb: AABB,
+    pub first: u32, // Left child for nodes, the right one follows it. First entry of BVH::indices for leaves
+    pub count: u32, // Triangles in a leaf, 0 for nodes
 }
 
+// Flat tree, the root is the first node. Empty for objects without triangles
 #[derive(Debug, Clone)]
 pub struct BVH {
-    pub aabb: AABB,
-    pub left: BVHNode,
-    pub right: BVHNode,
+    pub nodes: Vec<BVHNode>,
+    pub indices: Vec<u32>, // Into Object::triangles, every leaf covers a consecutive run
+}
+
+// Triangle as the builder sees it, bounds are computed once up front
+struct BuildTriangle {
+    index: usize,
+    aabb: AABB,
+    centroid: Vec3,
+}
+
+#[derive(Clone)]
+struct Bin {
+    aabb: AABB,
+    count: usize,
+}
+
+// Tree as it is built, subtrees are built in parallel and flattened afterwards
+enum BuildNode {
+    Leaf { aabb: AABB, first: usize, count: usize },
+    Node { aabb: AABB, left: Box<BuildNode>, right: Box<BuildNode> },
 }
 
 impl BVH {
-    pub fn generate_bottom(tris: &Vec<Arc<RwLock<Triangle>>>) -> Self {
-        println!("Triangles got into BVH generator: {}", tris.len());
-        if tris.is_empty() {
-            panic!("Cannot build BVH with zero triangles");
+    // Top-down binned SAH build, big subtrees are built on separate threads
+    pub fn build(tris: &[Arc<RwLock<Triangle>>]) -> Self {
+        let start = Instant::now();
+        let mut items: Vec<BuildTriangle> = tris.par_iter().enumerate().map(|(index, tri)| {
+            let aabb = triangle_aabb(&tri.read().unwrap());
+            BuildTriangle { index, centroid: aabb.center(), aabb }
+        }).collect();
+
+        let mut bvh = BVH { nodes: Vec::with_capacity(tris.len() * 2), indices: Vec::new() };
+        if !items.is_empty() {
+            let root = build_node(&mut items, 0, 1);
+            bvh.nodes.push(BVHNode { aabb: AABB::empty(), first: 0, count: 0 });
+            bvh.flatten(root, 0);
         }
+        bvh.indices = items.iter().map(|item| item.index as u32).collect();
+        println!("BVH: {}, built in {:.1} ms with {} rayon threads", bvh.stats(), start.elapsed().as_secs_f64() * 1000., rayon::current_num_threads());
+        bvh
+    }
 
-        let mut nodes: Vec<BVHNode> = tris.iter().map(|tri| BVHNode::Leaf(tri.clone())).collect();
-        println!("Converted {} triangles into {} leaves", tris.len(), nodes.len());
-        let mut leaves_processed = 0;
-
-        while nodes.len() > 2 {
-            let mut next_level = Vec::new();
-            let mut iter = nodes.into_iter();
-
-            while let Some(left) = iter.next() {
-                match iter.next() {
-                    Some(right) => {
-                        let left_aabb = match &left {
-                            BVHNode::Leaf(tri) => {leaves_processed += 1; AABB::new_from_tri(&vec![(tri.read().unwrap()).clone()])},
-                            BVHNode::Node(node) => node.aabb.clone(),
-                        };
-                        let right_aabb = match &right {
-                            BVHNode::Leaf(tri) => {leaves_processed += 1; AABB::new_from_tri(&vec![(tri.read().unwrap()).clone()])},
-                            BVHNode::Node(node) => node.aabb.clone(),
-                        };
-                        let parent = BVHNode::Node(
-                            Box::new(BVH {
-                                aabb: left_aabb.expand(&right_aabb),
-                                left,
-                                right,
-                            })
-                        );
-                        next_level.push(parent);
-                    }
-                    None => {
-                        next_level.push(left);
+    // Everything that decides the tree a build produces, cached trees built with other settings are stale
+    pub fn settings() -> String {
+        format!("bins {} traversal {} intersection {} leaf {} depth {}", SAH_BINS, TRAVERSAL_COST, INTERSECTION_COST, MAX_LEAF_TRIANGLES, MAX_DEPTH)
+    }
+
+    // Writes node into nodes[index], children are appended as pairs
+    fn flatten(&mut self, node: BuildNode, index: usize) {
+        match node {
+            BuildNode::Leaf { aabb, first, count } => self.nodes[index] = BVHNode { aabb, first: first as u32, count: count as u32 },
+            BuildNode::Node { aabb, left, right } => {
+                let first = self.nodes.len();
+                self.nodes.push(BVHNode { aabb: AABB::empty(), first: 0, count: 0 });
+                self.nodes.push(BVHNode { aabb: AABB::empty(), first: 0, count: 0 });
+                self.nodes[index] = BVHNode { aabb, first: first as u32, count: 0 };
+                self.flatten(*left, first);
+                self.flatten(*right, first + 1);
+            },
+        }
+    }
+
+    // Recomputes bounds bottom-up after triangles were moved, the tree itself is kept.
+    // Tracing gets slower the further triangles move from where the tree was built, rebuild then
+    pub fn refit(&mut self, triangles: &[Arc<RwLock<Triangle>>]) {
+        // Children always come after their parent, so walking backwards visits them first
+        for index in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[index];
+            let aabb = if node.is_leaf() {
+                self.indices[node.first as usize..(node.first + node.count) as usize].iter()
+                    .fold(AABB::empty(), |aabb, &triangle| aabb.expand(&triangle_aabb(&triangles[triangle as usize].read().unwrap())))
+            } else {
+                self.nodes[node.first as usize].aabb.expand(&self.nodes[node.first as usize + 1].aabb)
+            };
+            self.nodes[index].aabb = aabb;
+        }
+    }
+
+    // Adds triangles[index] without rebuilding, next to the leaf whose bounds grow the least
+    pub fn insert(&mut self, triangles: &[Arc<RwLock<Triangle>>], index: usize) {
+        let aabb = triangle_aabb(&triangles[index].read().unwrap());
+        if self.nodes.is_empty() {
+            self.nodes.push(BVHNode { aabb, first: self.indices.len() as u32, count: 1 });
+            self.indices.push(index as u32);
+            return;
+        }
+
+        let mut node = 0;
+        let mut depth = 1;
+        while !self.nodes[node].is_leaf() {
+            self.nodes[node].aabb = self.nodes[node].aabb.expand(&aabb);
+            let growth = |child: usize| self.nodes[child].aabb.expand(&aabb).surface_area() - self.nodes[child].aabb.surface_area();
+            let left = self.nodes[node].first as usize;
+            node = if growth(left) <= growth(left + 1) { left } else { left + 1 };
+            depth += 1;
+        }
+
+        let leaf = self.nodes[node].clone();
+        // The leaf at the end of indices can grow in place while it is small, consecutive inserts often land there
+        if (leaf.first + leaf.count) as usize == self.indices.len() && (leaf.count as usize) < MAX_LEAF_TRIANGLES {
+            self.nodes[node] = BVHNode { aabb: leaf.aabb.expand(&aabb), first: leaf.first, count: leaf.count + 1 };
+            self.indices.push(index as u32);
+            return;
+        }
+        if depth >= MAX_DEPTH {
+            *self = Self::build(triangles);
+            return;
+        }
+        // Otherwise the leaf moves down next to a new one holding just this triangle
+        let first = self.nodes.len() as u32;
+        self.nodes.push(leaf.clone());
+        self.nodes.push(BVHNode { aabb: aabb.clone(), first: self.indices.len() as u32, count: 1 });
+        self.indices.push(index as u32);
+        self.nodes[node] = BVHNode { aabb: leaf.aabb.expand(&aabb), first, count: 0 };
+    }
+
+    // Closest triangle hit, triangles are placed at origin. Nearer children are visited first and anything further
+    // than the closest hit so far is skipped
+    pub fn closest_hit(&self, triangles: &[Arc<RwLock<Triangle>>], origin: Vec3, ray: &Ray) -> Option<IntersectionResult> {
+        // Node bounds are relative to the origin
+        let local_ray = Ray { origin: ray.origin - origin, direction: ray.direction };
+        let inv_direction = Vec3::ONE / ray.direction;
+        let mut closest: Option<IntersectionResult> = None;
+        let mut closest_distance = f32::INFINITY;
+
+        let mut stack = [0u32; MAX_DEPTH + 1];
+        let mut stack_size = 0;
+        if self.nodes.first().is_some_and(|root| root.aabb.ray_distance(&local_ray, inv_direction).is_some()) {
+            stack_size = 1;
+        }
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node = &self.nodes[stack[stack_size] as usize];
+            if node.is_leaf() {
+                for &index in &self.indices[node.first as usize..(node.first + node.count) as usize] {
+                    let hit = triangles[index as usize].read().unwrap().intersects_ray(&origin, ray);
+                    if let Some(hit) = hit.filter(|hit| hit.distance < closest_distance) {
+                        closest_distance = hit.distance;
+                        closest = Some(hit);
                     }
                 }
+                continue;
             }
-            nodes = next_level;
-        };
 
-        println!("Processed leaves while building BVH: {}", leaves_processed);
-        println!("final nodes length: {}", nodes.len());
+            let distance = |child: u32| self.nodes[child as usize].aabb.ray_distance(&local_ray, inv_direction).filter(|distance| *distance <= closest_distance);
+            push_children(&mut stack, &mut stack_size, (node.first, distance(node.first)), (node.first + 1, distance(node.first + 1)));
+        }
+        closest
+    }
 
-        if tris.len() <= 2 { // FIXME: quick hack that doesn't work for a case with 2 tris
-            return BVH {
-                aabb: AABB::new_from_tri(&vec![tris[0].read().unwrap().clone()]),
-                left: BVHNode::Leaf(tris[0].clone()),
-                right: BVHNode::Leaf(tris[0].clone()),
+    pub fn stats(&self) -> BVHStats {
+        let mut stats = BVHStats { sah_cost: 0., depth: 0, nodes: 0, leaves: 0, triangles: 0, min_leaf: 0, max_leaf: 0 };
+        let Some(root) = self.nodes.first() else { return stats };
+        let root_area = root.aabb.surface_area().max(f32::MIN_POSITIVE);
+        stats.min_leaf = usize::MAX;
+
+        let mut stack = vec![(0, 1)]; // Node and its depth
+        while let Some((index, depth)) = stack.pop() {
+            let node: &BVHNode = &self.nodes[index];
+            let area = node.aabb.surface_area() / root_area;
+            stats.depth = stats.depth.max(depth);
+            if node.is_leaf() {
+                let count = node.count as usize;
+                stats.leaves += 1;
+                stats.triangles += count;
+                stats.min_leaf = stats.min_leaf.min(count);
+                stats.max_leaf = stats.max_leaf.max(count);
+                stats.sah_cost += INTERSECTION_COST * count as f32 * area;
+            } else {
+                stats.nodes += 1;
+                stats.sah_cost += TRAVERSAL_COST * area;
+                stack.push((node.first as usize, depth + 1));
+                stack.push((node.first as usize + 1, depth + 1));
             }
         }
+        stats
+    }
+}
 
-        let final_left = nodes.pop().unwrap();
-        let final_right = nodes.pop().unwrap();
-        if let BVHNode::Node(bvh_left) = &final_left {
-            if let BVHNode::Node(bvh_right) = &final_right {
-                let aabb = bvh_left.aabb.expand(&bvh_right.aabb);
-                return BVH {
-                    aabb,
-                    left: final_left,
-                    right: final_right,
-                };
-            }
+impl BVHNode {
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+// Shared by BVH and TLAS traversal. Children come with their distance along the ray, None if it misses them or they are
+// further than the closest hit. The far one is pushed first so the near one is popped next, the children of the far
+// one are checked against the closest hit found by then
+pub fn push_children<T: Copy>(stack: &mut [T], stack_size: &mut usize, a: (T, Option<f32>), b: (T, Option<f32>)) {
+    let (near, far) = match (a.1, b.1) {
+        (None, _) => (b, a),
+        (Some(a_distance), Some(b_distance)) if b_distance < a_distance => (b, a),
+        _ => (a, b),
+    };
+    for (child, distance) in [far, near] {
+        if distance.is_some() {
+            stack[*stack_size] = child;
+            *stack_size += 1;
+        }
+    }
+}
+
+// Splits until SAH prefers a leaf. The triangles are reordered in place, offset is where they start in the whole set
+fn build_node(items: &mut [BuildTriangle], offset: usize, depth: usize) -> BuildNode {
+    let aabb = bounds(items, |item| item.aabb.clone());
+    let leaf_cost = INTERSECTION_COST * items.len() as f32;
+    let split = if depth >= MAX_DEPTH { None } else { find_split(items, &aabb) };
+
+    let split = match split {
+        Some((cost, _)) if cost >= leaf_cost && items.len() <= MAX_LEAF_TRIANGLES => None,
+        Some((_, middle)) => Some(middle),
+        // Every centroid is in the same spot, bins can't separate them so the set is just halved
+        None if items.len() > MAX_LEAF_TRIANGLES && depth < MAX_DEPTH => Some(items.len() / 2),
+        None => None,
+    };
+    let Some(middle) = split else {
+        return BuildNode::Leaf { aabb, first: offset, count: items.len() };
+    };
+
+    let parallel = items.len() >= PARALLEL_TRIANGLES;
+    let (left_items, right_items) = items.split_at_mut(middle);
+    let (left, right) = if parallel {
+        rayon::join(|| build_node(left_items, offset, depth + 1), || build_node(right_items, offset + middle, depth + 1))
+    } else {
+        (build_node(left_items, offset, depth + 1), build_node(right_items, offset + middle, depth + 1))
+    };
+    BuildNode::Node { aabb, left: Box::new(left), right: Box::new(right) }
+}
+
+// Union of the boxes, spread over threads for big sets
+fn bounds(items: &[BuildTriangle], aabb: impl Fn(&BuildTriangle) -> AABB + Sync) -> AABB {
+    if items.len() >= PARALLEL_TRIANGLES {
+        items.par_iter().fold(AABB::empty, |bounds, item| bounds.expand(&aabb(item))).reduce(AABB::empty, |a, b| a.expand(&b))
+    } else {
+        items.iter().fold(AABB::empty(), |bounds, item| bounds.expand(&aabb(item)))
+    }
+}
+
+// Returns the SAH cost of the best split and how many triangles go left, after partitioning them
+fn find_split(items: &mut [BuildTriangle], aabb: &AABB) -> Option<(f32, usize)> {
+    let centroids = bounds(items, |item| AABB { min: item.centroid, max: item.centroid });
+    let extent = centroids.max - centroids.min;
+    let parent_area = aabb.surface_area().max(f32::MIN_POSITIVE);
+    let bin_of = |centroid: Vec3, axis: usize| (((centroid[axis] - centroids.min[axis]) / extent[axis] * SAH_BINS as f32) as usize).min(SAH_BINS - 1);
+
+    let mut best: Option<(f32, usize, usize)> = None; // Cost, axis and the first bin on the right
+    for axis in 0..3 {
+        if extent[axis] <= 0. {
+            continue;
+        }
+        let empty_bins = || vec![Bin { aabb: AABB::empty(), count: 0 }; SAH_BINS];
+        let add = |mut bins: Vec<Bin>, item: &BuildTriangle| {
+            let bin = &mut bins[bin_of(item.centroid, axis)];
+            bin.aabb = bin.aabb.expand(&item.aabb);
+            bin.count += 1;
+            bins
         };
-        panic!("Error building BVH")
+        let bins = if items.len() >= PARALLEL_TRIANGLES {
+            items.par_iter().fold(empty_bins, add).reduce(empty_bins, |a, b| {
+                a.into_iter().zip(b).map(|(a, b)| Bin { aabb: a.aabb.expand(&b.aabb), count: a.count + b.count }).collect()
+            })
+        } else {
+            items.iter().fold(empty_bins(), add)
+        };
+
+        // Sweeping from the right gives the area and count of everything past each border
+        let mut right_area = [0.; SAH_BINS];
+        let mut right_count = [0; SAH_BINS];
+        let mut right = Bin { aabb: AABB::empty(), count: 0 };
+        for i in (1..SAH_BINS).rev() {
+            right.aabb = right.aabb.expand(&bins[i].aabb);
+            right.count += bins[i].count;
+            right_area[i] = right.aabb.surface_area();
+            right_count[i] = right.count;
+        }
+        let mut left = Bin { aabb: AABB::empty(), count: 0 };
+        for i in 1..SAH_BINS {
+            left.aabb = left.aabb.expand(&bins[i - 1].aabb);
+            left.count += bins[i - 1].count;
+            if left.count == 0 || right_count[i] == 0 {
+                continue;
+            }
+            let cost = TRAVERSAL_COST + INTERSECTION_COST * (left.aabb.surface_area() * left.count as f32 + right_area[i] * right_count[i] as f32) / parent_area;
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, i));
+            }
+        }
+    }
+
+    let (cost, axis, split_bin) = best?;
+    let mut middle = 0;
+    for i in 0..items.len() {
+        if bin_of(items[i].centroid, axis) < split_bin {
+            items.swap(i, middle);
+            middle += 1;
+        }
+    }
+    Some((cost, middle))
+}
+
+fn triangle_aabb(triangle: &Triangle) -> AABB {
+    triangle.vertices.iter().fold(AABB::empty(), |aabb, vertex| aabb.include_point(vertex.pos))
+}
+
+// Tree quality, lower SAH cost means fewer expected node visits and triangle tests per ray
+#[derive(Debug, Clone)]
+pub struct BVHStats {
+    pub sah_cost: f32,
+    pub depth: usize, // Longest path from the root to a leaf, counting both
+    pub nodes: usize, // Inner nodes only
+    pub leaves: usize,
+    pub triangles: usize,
+    pub min_leaf: usize,
+    pub max_leaf: usize,
+}
+
+impl fmt::Display for BVHStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} triangles, {} nodes, {} leaves ({}-{} triangles, {:.1} on average), depth {}, SAH cost {:.2}",
+            self.triangles, self.nodes, self.leaves, self.min_leaf, self.max_leaf,
+            self.triangles as f32 / self.leaves.max(1) as f32, self.depth, self.sah_cost)
     }
 }
@@ -0,0 +1,129 @@
+use glam::Vec3;
+
+use crate::aabb::AABB;
+use crate::aabb::bvh::push_children;
+use crate::object::Object;
+use crate::ray::{ Ray, IntersectionResult };
+
+// Median splits halve the objects on every level, so the traversal stack never gets this deep
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone)]
+enum TLASNode {
+    Node { aabb: AABB, left: usize, right: usize }, // Children are indices into TLAS::nodes
+    Leaf { aabb: AABB, object: usize }, // Index into Scene::objects
+}
+
+// Top level BVH over the world space bounds of every object in a scene, object BVHs sit below its leaves.
+// Building only sorts object bounds, so it is simply rebuilt whenever an object moves
+#[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)] // Named like AABB and BVH
+pub struct TLAS {
+    nodes: Vec<TLASNode>, // Root is the first one, empty when there are no objects
+    bounds: Vec<AABB>, // World bounds of the objects it was built from
+}
+
+impl TLAS {
+    pub fn build(objects: &[Object]) -> Self {
+        let bounds: Vec<AABB> = objects.iter().map(world_bounds).collect();
+        let mut tlas = Self { nodes: Vec::with_capacity(objects.len() * 2), bounds };
+        let mut indices: Vec<usize> = (0..objects.len()).collect();
+        if !indices.is_empty() {
+            tlas.build_node(&mut indices);
+        }
+        tlas
+    }
+
+    // Rebuilds if objects were added, removed or moved since the last build
+    pub fn update(&mut self, objects: &[Object]) {
+        if !self.is_current(objects) {
+            *self = Self::build(objects);
+        }
+    }
+
+    pub fn is_current(&self, objects: &[Object]) -> bool {
+        self.bounds.len() == objects.len() && self.bounds.iter().zip(objects).all(|(bounds, object)| *bounds == world_bounds(object))
+    }
+
+    // Returns the index of the node it pushed
+    fn build_node(&mut self, indices: &mut [usize]) -> usize {
+        let aabb = indices.iter().fold(AABB::empty(), |aabb, &object| aabb.expand(&self.bounds[object]));
+        let index = self.nodes.len();
+        if indices.len() == 1 {
+            self.nodes.push(TLASNode::Leaf { aabb, object: indices[0] });
+            return index;
+        }
+
+        // Halves along the axis where object centers are spread the most
+        let centers = indices.iter().fold(AABB::empty(), |centers, &object| centers.include_point(self.bounds[object].center()));
+        let extent = centers.max - centers.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+        let middle = indices.len() / 2;
+        indices.select_nth_unstable_by(middle, |&a, &b| self.bounds[a].center()[axis].total_cmp(&self.bounds[b].center()[axis]));
+
+        self.nodes.push(TLASNode::Node { aabb: aabb.clone(), left: 0, right: 0 });
+        let (left_indices, right_indices) = indices.split_at_mut(middle);
+        let left = self.build_node(left_indices);
+        let right = self.build_node(right_indices);
+        self.nodes[index] = TLASNode::Node { aabb, left, right };
+        index
+    }
+
+    // Nearer children are visited first and anything further than the closest hit so far is skipped
+    pub fn closest_hit<'a>(&self, objects: &'a [Object], ray: &Ray) -> Option<(&'a Object, IntersectionResult)> {
+        let inv_direction = Vec3::ONE / ray.direction;
+        let mut closest: Option<(&Object, IntersectionResult)> = None;
+        let closest_distance = |closest: &Option<(&Object, IntersectionResult)>| closest.as_ref().map_or(f32::INFINITY, |(_, hit)| hit.distance);
+
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut stack_size = 0;
+        if self.nodes.first().is_some_and(|root| root.aabb().ray_distance(ray, inv_direction).is_some()) {
+            stack_size = 1;
+        }
+        while stack_size > 0 {
+            stack_size -= 1;
+            match &self.nodes[stack[stack_size]] {
+                TLASNode::Leaf { object, .. } => {
+                    let object = &objects[*object];
+                    if let Some(hit) = object.calculate_intersection(ray) {
+                        if hit.distance < closest_distance(&closest) {
+                            closest = Some((object, hit));
+                        }
+                    }
+                },
+                TLASNode::Node { left, right, .. } => {
+                    let max_distance = closest_distance(&closest);
+                    let distance = |node: usize| self.nodes[node].aabb().ray_distance(ray, inv_direction).filter(|distance| *distance <= max_distance);
+                    push_children(&mut stack, &mut stack_size, (*left, distance(*left)), (*right, distance(*right)));
+                },
+            }
+        }
+        closest
+    }
+}
+
+// What rays are traced against, the objects together with the TLAS built over them
+#[derive(Clone, Copy)]
+pub struct Geometry<'a> {
+    pub objects: &'a [Object],
+    pub tlas: &'a TLAS,
+}
+
+impl<'a> Geometry<'a> {
+    pub fn closest_hit(&self, ray: &Ray) -> Option<(&'a Object, IntersectionResult)> {
+        self.tlas.closest_hit(self.objects, ray)
+    }
+}
+
+impl TLASNode {
+    fn aabb(&self) -> &AABB {
+        match self {
+            TLASNode::Node { aabb, .. } | TLASNode::Leaf { aabb, .. } => aabb,
+        }
+    }
+}
+
+// Object bounds are stored relative to its origin
+fn world_bounds(object: &Object) -> AABB {
+    object.aabb.translate(object.origin)
+}
@@ -2,69 +2,184 @@ use glam::{ Vec2, Vec3, Quat };
 use derive_new::new;
 use rayon::prelude::*;
 
+use crate::helper::linear_to_ARGBu32;
 use crate::ray::Ray;
-use crate::object::Object;
-use crate::screen::{ ScreenBuffers, ScreenBuffersPixel };
+use crate::aabb::tlas::Geometry;
+use crate::screen::ScreenBuffers;
+use crate::texture::Texture;
+use crate::sampler::{ Sampler, concentric_disk };
+use crate::integrator::PathTracer;
 
-#[derive(new)]
+// Which image axis Camera::fov spans
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FovAxis {
+    Horizontal,
+    Vertical,
+}
+
+// Rectilinear projection can't show 180deg or more, wider views switch to an equidistant fisheye
+const FISHEYE_FOV: f64 = 180.;
+const MAX_FOV: f64 = 360.;
+const MIN_FOV: f64 = 1.;
+
+#[derive(new, Clone, PartialEq)]
 pub struct Camera {
     origin: Vec3,
     rotation: Vec3, // Yaw, Pitch, Roll // from -Z
     #[new(value = "90.")]
-    #[allow(dead_code)] // will be used in future to calculate fov
-    pub fov: f64,
+    pub fov: f64, // Degrees
+    #[new(value = "FovAxis::Vertical")]
+    pub fov_axis: FovAxis,
     #[new(value = "false")]
     pub orthographic: bool,
+    #[new(value = "5.12")]
+    pub ortho_size: f32, // Visible extent in world units along fov_axis when orthographic
+    #[new(value = "2.56")]
+    pub focus_distance: f32, // Plane in sharp focus, also keeps its size when switching between projections
+    #[new(value = "0.")]
+    pub aperture: f32, // Lens radius, 0 is a pinhole camera without depth of field
 }
 
 impl Camera {
-    pub fn render(&self, objects: &Vec<Object>, screen: &mut ScreenBuffers) {
-        use crate::{ WIDTH, HEIGHT };
+    pub fn render(&self, geometry: Geometry, tracer: &PathTracer, screen: &mut ScreenBuffers, frame: u64) {
+        let (width, height) = (screen.width(), screen.height());
         screen.pixels.par_iter_mut().enumerate().for_each(|(i, pixel)| {
-            let x = i % WIDTH;
-            let y = i / WIDTH;
-            
-            let rel_x = x as f32 / (WIDTH as f32/2.0) - 1.0;
-            let rel_y = -(y as f32 / (HEIGHT as f32/2.0) - 1.0);
-            // Thought: is it neseccary to store relative x and then multiply it by aspect ratio, or is better to right away calculate it with aspect ratio?
-            
-            let pixel_pointer = pixel as *mut ScreenBuffersPixel;
-            let ray = self.generate_ray(Vec2::new(rel_x, rel_y));
+            let mut sampler = Sampler::new(i as u64, frame);
+            let (radiance, distance) = self.render_pixel(geometry, tracer, (i % width, i / width), (width, height), tracer.samples_per_pixel, &mut sampler);
 
-            objects.iter().for_each(|object| {
-                object.calculate_intersection(&ray, pixel_pointer);
-            });
+            pixel.alpha = distance;
+            pixel.color = radiance;
+            pixel.rendered = linear_to_ARGBu32(pixel.color);
         });
     }
 
-    pub fn generate_ray(&self, offset: Vec2) -> Ray {
-        if !self.orthographic {
-            self.generate_ray_perspective(offset)
+    // Renders into the texture at its own resolution, averaging `samples` jittered paths per pixel
+    pub fn render_to_texture(&self, geometry: Geometry, tracer: &PathTracer, texture: &Texture, samples: u32) {
+        let (width, height) = (texture.size_x(), texture.size_y());
+        texture.get_pixel_par_iterator().enumerate().for_each(|(i, pixel)| {
+            let mut sampler = Sampler::new(i as u64, 0);
+            let (radiance, _) = self.render_pixel(geometry, tracer, (i % width, i / width), (width, height), samples.max(1), &mut sampler);
+            pixel.write().unwrap().argb_set(linear_to_ARGBu32(radiance));
+        });
+    }
+
+    // Average radiance and closest primary hit distance of one pixel
+    fn render_pixel(&self, geometry: Geometry, tracer: &PathTracer, (x, y): (usize, usize), (width, height): (usize, usize), samples: u32, sampler: &mut Sampler) -> (Vec3, f32) {
+        let aspect_ratio = width as f32 / height as f32;
+        let mut radiance = Vec3::ZERO;
+        let mut distance = f32::INFINITY;
+        for _ in 0..samples {
+            // Jitter inside of the pixel so multiple samples antialias the image
+            let jitter = sampler.next_vec2();
+            let rel_x = (x as f32 + jitter.x) / (width as f32/2.0) - 1.0;
+            let rel_y = -((y as f32 + jitter.y) / (height as f32/2.0) - 1.0);
+            // Thought: is it neseccary to store relative x and then multiply it by aspect ratio, or is better to right away calculate it with aspect ratio?
+
+            let ray = self.generate_ray(Vec2::new(rel_x, rel_y), aspect_ratio, sampler.next_vec2());
+            let result = tracer.trace(geometry, ray, sampler);
+            radiance += result.radiance;
+            distance = distance.min(result.distance);
+        }
+        (radiance / samples as f32, distance)
+    }
+
+    // lens_sample picks the point on the lens disk, (0.5, 0.5) is its center
+    pub fn generate_ray(&self, offset: Vec2, aspect_ratio: f32, lens_sample: Vec2) -> Ray {
+        let pinhole_ray = if !self.orthographic {
+            self.generate_ray_perspective(offset, aspect_ratio)
         } else {
-            self.generate_ray_orthographic(offset)
+            self.generate_ray_orthographic(offset, aspect_ratio)
+        };
+        if self.aperture <= 0. {
+            return pinhole_ray;
         }
+
+        // Every ray through the lens converges where the pinhole ray crosses the focus plane
+        let along_front = pinhole_ray.direction.dot(self.front());
+        let focus_point = if along_front > f32::EPSILON {
+            pinhole_ray.at(self.focus_distance / along_front)
+        } else {
+            pinhole_ray.at(self.focus_distance) // Fisheye rays at or behind the side focus on a sphere instead
+        };
+        let lens = concentric_disk(lens_sample) * self.aperture;
+        let lens_origin = pinhole_ray.origin + self.right()*lens.x + self.up()*lens.y;
+        Ray::new(lens_origin, focus_point - lens_origin)
     }
 
-    fn generate_ray_perspective(&self, offset: Vec2) -> Ray {
-        use crate::ASPECT_RATIO;
-        let (relative_x, relative_y) = (offset.x, offset.y);
-        let ray_direction = {
-            //          TODO: This is hardcoded and incorrect (at least for fov >= 180deg)
-            self.front() + // FOV 90
-            self.up()*relative_y +
-            self.right()*relative_x*ASPECT_RATIO
+    // Distance along the view direction to whatever is visible through the pixel, e.g. for focusing on it
+    pub fn depth_at(&self, screen: &ScreenBuffers, x: usize, y: usize) -> Option<f32> {
+        if x >= screen.width() || y >= screen.height() {
+            return None;
+        }
+        let distance = screen.pixels[x + y*screen.width()].alpha;
+        if !distance.is_finite() {
+            return None;
+        }
+        if self.orthographic {
+            return Some(distance);
+        }
+        let rel_x = (x as f32 + 0.5) / (screen.width() as f32/2.0) - 1.0;
+        let rel_y = -((y as f32 + 0.5) / (screen.height() as f32/2.0) - 1.0);
+        let aspect_ratio = screen.width() as f32 / screen.height() as f32;
+        let direction = self.generate_ray_perspective(Vec2::new(rel_x, rel_y), aspect_ratio).direction;
+        // Fisheye pixels looking sideways or backwards have no depth in front of the lens to focus on
+        let along_front = direction.dot(self.front());
+        if along_front <= 0. {
+            return None;
+        }
+        Some(distance * along_front)
+    }
+
+    // Stretch offset so that the axis fov is measured along spans -1..1 and the other one keeps pixels square
+    fn offset_along_fov_axis(&self, offset: Vec2, aspect_ratio: f32) -> Vec2 {
+        match self.fov_axis {
+            FovAxis::Horizontal => Vec2::new(offset.x, offset.y / aspect_ratio),
+            FovAxis::Vertical => Vec2::new(offset.x * aspect_ratio, offset.y),
+        }
+    }
+
+    fn generate_ray_perspective(&self, offset: Vec2, aspect_ratio: f32) -> Ray {
+        let fov = self.fov.clamp(MIN_FOV, MAX_FOV);
+        let half_fov = (fov as f32 / 2.).to_radians();
+        let offset = self.offset_along_fov_axis(offset, aspect_ratio);
+
+        let ray_direction = if fov < FISHEYE_FOV {
+            let scale = half_fov.tan();
+            self.front() +
+            self.up()*offset.y*scale +
+            self.right()*offset.x*scale
+        } else {
+            // Angle from the view direction grows linearly with distance from the image center
+            let angles = offset * half_fov;
+            let theta = angles.length();
+            if theta < f32::EPSILON {
+                self.front()
+            } else {
+                let side = (self.right()*angles.x + self.up()*angles.y) / theta;
+                self.front()*theta.cos() + side*theta.sin()
+            }
         };
-        return Ray::new(self.origin, ray_direction);
+        Ray::new(self.origin, ray_direction)
     }
 
-    fn generate_ray_orthographic(&self, offset: Vec2) -> Ray {
-        use crate::ASPECT_RATIO;
-        let (relative_x, relative_y) = (offset.x, offset.y);
-        // TODO: Orthographic camera scale instead of const
-        const CAMERA_SCALE: f32 = 2.56;
-        let ray_relative_position = self.right()*CAMERA_SCALE*relative_x*ASPECT_RATIO as f32 + self.up()*CAMERA_SCALE*relative_y as f32;
+    fn generate_ray_orthographic(&self, offset: Vec2, aspect_ratio: f32) -> Ray {
+        let offset = self.offset_along_fov_axis(offset, aspect_ratio) * self.ortho_size / 2.;
+        let ray_relative_position = self.right()*offset.x + self.up()*offset.y;
         let ray_origin = self.origin + ray_relative_position;
-        return Ray::new(ray_origin, self.front());
+        Ray::new(ray_origin, self.front())
+    }
+
+    // Switches projection so that objects at focus_distance keep the same size on screen
+    pub fn toggle_orthographic(&mut self) {
+        let focus_distance = self.focus_distance.max(f32::EPSILON);
+        if self.orthographic {
+            let half_fov = (self.ortho_size / 2. / focus_distance).atan();
+            self.fov = (half_fov.to_degrees() as f64 * 2.).clamp(MIN_FOV, FISHEYE_FOV - MIN_FOV);
+        } else if self.fov < FISHEYE_FOV {
+            let half_fov = (self.fov as f32 / 2.).to_radians();
+            self.ortho_size = half_fov.tan() * focus_distance * 2.;
+        }
+        self.orthographic = !self.orthographic;
     }
 
     pub fn translate(&mut self, offset: Vec3) {
@@ -73,6 +188,14 @@ impl Camera {
     pub fn rotate(&mut self, angle: Vec3) {
         self.rotation += angle;
     }
+    // Multiplies field of view or orthographic size, values below 1 zoom in
+    pub fn zoom(&mut self, factor: f32) {
+        if self.orthographic {
+            self.ortho_size = (self.ortho_size * factor).max(f32::EPSILON);
+        } else {
+            self.fov = (self.fov * factor as f64).clamp(MIN_FOV, MAX_FOV);
+        }
+    }
 
     fn calculate_rotation(&self, dir: Vec3) -> Vec3 {
         let deg_to_rad = 57.2957795131;
@@ -89,6 +212,7 @@ impl Camera {
     pub fn right(&self) -> Vec3 {self.calculate_rotation(Vec3::X)}
     pub fn left(&self) -> Vec3 {self.calculate_rotation(Vec3::NEG_X)}
 
+    pub fn origin(&self) -> Vec3 {return self.origin;}
     pub fn yaw(&self) -> f32 {return self.rotation.x;}
     pub fn pitch(&self) -> f32 {return self.rotation.y;}
     pub fn roll(&self) -> f32 {return self.rotation.z;}
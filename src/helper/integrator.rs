@@ -0,0 +1,73 @@
+use glam::Vec3;
+use derive_new::new;
+
+use crate::ray::Ray;
+use crate::aabb::tlas::Geometry;
+use crate::sampler::Sampler;
+
+// Offset along the normal for bounce rays so they don't hit the surface they start from
+const RAY_EPSILON: f32 = 1e-4;
+
+#[derive(new, Clone)]
+pub struct PathTracer {
+    #[new(value = "8")]
+    pub max_depth: u32,
+    #[new(value = "3")]
+    pub russian_roulette_depth: u32, // Bounces that are always traced before paths start being terminated randomly
+    #[new(value = "1")]
+    pub samples_per_pixel: u32,
+    #[new(value = "Vec3::new(1.0, 1.0, 1.0)")]
+    pub sky_zenith: Vec3,
+    #[new(value = "Vec3::new(0.5, 0.6, 0.8)")]
+    pub sky_horizon: Vec3,
+}
+
+pub struct TraceResult {
+    pub radiance: Vec3,
+    pub distance: f32, // Distance to the first hit, INFINITY if primary ray missed
+}
+
+impl PathTracer {
+    pub fn trace(&self, geometry: Geometry, primary_ray: Ray, sampler: &mut Sampler) -> TraceResult {
+        let mut radiance = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        let mut ray = primary_ray;
+        let mut first_distance = f32::INFINITY;
+
+        for depth in 0..self.max_depth {
+            let Some((object, hit)) = geometry.closest_hit(&ray) else {
+                radiance += throughput * self.background(&ray);
+                break;
+            };
+            if depth == 0 {
+                first_distance = hit.distance;
+            }
+
+            let material = &object.materials[hit.material];
+            radiance += throughput * material.emitted();
+
+            let Some(sample) = material.sample(ray.direction, &hit, sampler) else {
+                break; // Path was absorbed
+            };
+            throughput *= sample.weight;
+            // Offset to the side of the surface the new ray travels to, so refracted rays start inside
+            let offset = hit.normal * RAY_EPSILON * sample.direction.dot(hit.normal).signum();
+            ray = Ray::new(hit.position + offset, sample.direction);
+
+            if depth + 1 >= self.russian_roulette_depth {
+                let survival = throughput.max_element().min(0.95);
+                if sampler.next_f32() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+
+        TraceResult { radiance, distance: first_distance }
+    }
+
+    fn background(&self, ray: &Ray) -> Vec3 {
+        let t = ray.direction.y.max(0.);
+        self.sky_horizon.lerp(self.sky_zenith, t)
+    }
+}
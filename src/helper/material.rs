@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use glam::{ Vec2, Vec3 };
+
+use crate::ray::IntersectionResult;
+use crate::sampler::{ Sampler, cosine_hemisphere };
+use crate::texture::Texture;
+
+#[derive(Clone, Debug)]
+pub enum Bsdf {
+    Lambertian,
+    Metal { roughness: f32 },
+    Dielectric { ior: f32 },
+    Emissive, // Only emits light, every incoming path is absorbed
+}
+
+#[derive(Clone)]
+pub struct Material {
+    pub bsdf: Bsdf,
+    pub albedo: Vec3, // Linear color. Diffuse color, metal reflectance or glass tint depending on bsdf
+    pub albedo_texture: Option<Arc<Texture>>, // Multiplied with albedo when present
+    pub bump_texture: Option<Arc<Texture>>, // Height map perturbing the shading normal
+    pub bump_scale: f32, // Normal tilt per unit of height difference between neighbouring texels
+    pub emission: Vec3,
+}
+
+pub struct BsdfSample {
+    pub direction: Vec3,
+    pub weight: Vec3, // bsdf * cos / pdf
+}
+
+impl Material {
+    pub fn lambertian(albedo: Vec3) -> Self {
+        Self { bsdf: Bsdf::Lambertian, albedo, albedo_texture: None, bump_texture: None, bump_scale: 1., emission: Vec3::ZERO }
+    }
+
+    pub fn metal(albedo: Vec3, roughness: f32) -> Self {
+        Self { bsdf: Bsdf::Metal { roughness: roughness.clamp(0., 1.) }, albedo, albedo_texture: None, bump_texture: None, bump_scale: 1., emission: Vec3::ZERO }
+    }
+
+    pub fn dielectric(ior: f32) -> Self {
+        Self { bsdf: Bsdf::Dielectric { ior }, albedo: Vec3::ONE, albedo_texture: None, bump_texture: None, bump_scale: 1., emission: Vec3::ZERO }
+    }
+
+    pub fn emissive(color: Vec3, strength: f32) -> Self {
+        Self { bsdf: Bsdf::Emissive, albedo: Vec3::ZERO, albedo_texture: None, bump_texture: None, bump_scale: 1., emission: color * strength }
+    }
+
+    pub fn albedo_at(&self, hit: &IntersectionResult) -> Vec3 {
+        let albedo = self.albedo * hit.color;
+        match &self.albedo_texture {
+            Some(texture) => albedo * texture.sample(hit.uv),
+            None => albedo,
+        }
+    }
+
+    // Shading normal tilted by the bump map, `normal` is expected to face the incoming ray
+    fn bumped_normal(&self, hit: &IntersectionResult, normal: Vec3) -> Vec3 {
+        let Some(bump) = &self.bump_texture else {
+            return normal;
+        };
+        let texel = Vec2::new(1. / bump.size_x() as f32, 1. / bump.size_y() as f32);
+        let height = bump.sample_height(hit.uv);
+        let du = bump.sample_height(hit.uv + Vec2::new(texel.x, 0.)) - height;
+        let dv = bump.sample_height(hit.uv + Vec2::new(0., texel.y)) - height;
+        let gradient = hit.tangent.normalize_or_zero()*du + hit.bitangent.normalize_or_zero()*dv;
+        (normal - gradient*self.bump_scale).try_normalize().unwrap_or(normal)
+    }
+
+    pub fn emitted(&self) -> Vec3 {
+        self.emission
+    }
+
+    pub fn sample(&self, incoming: Vec3, hit: &IntersectionResult, sampler: &mut Sampler) -> Option<BsdfSample> {
+        let front_face = hit.normal.dot(incoming) < 0.;
+        let geometric_normal = if front_face { hit.normal } else { -hit.normal };
+        let normal = if hit.shading_normal.dot(geometric_normal) < 0. { -hit.shading_normal } else { hit.shading_normal };
+        let normal = self.bumped_normal(hit, normal);
+
+        match self.bsdf {
+            Bsdf::Lambertian => {
+                // Cosine term and pdf cancel each other out, leaving only albedo
+                let direction = cosine_hemisphere(normal, sampler.next_vec2());
+                if direction.dot(geometric_normal) <= 0. {
+                    return None; // Smooth normal pointed the sample into the surface
+                }
+                Some(BsdfSample { direction, weight: self.albedo_at(hit) })
+            },
+            Bsdf::Metal { roughness } => {
+                let reflected = reflect(incoming, normal);
+                let direction = (reflected + random_in_unit_sphere(sampler) * roughness).normalize();
+                if direction.dot(geometric_normal) <= 0. {
+                    return None; // Fuzzed below the surface
+                }
+                Some(BsdfSample { direction, weight: self.albedo_at(hit) })
+            },
+            Bsdf::Dielectric { ior } => {
+                let eta = if front_face { 1. / ior } else { ior };
+                let cos_theta = (-incoming).dot(normal).min(1.);
+                let sin_theta = (1. - cos_theta*cos_theta).sqrt();
+
+                let direction = if eta * sin_theta > 1. || schlick(cos_theta, eta) > sampler.next_f32() {
+                    reflect(incoming, normal)
+                } else {
+                    refract(incoming, normal, eta, cos_theta)
+                };
+                Some(BsdfSample { direction, weight: self.albedo_at(hit) })
+            },
+            Bsdf::Emissive => None,
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::lambertian(Vec3::splat(0.8))
+    }
+}
+
+impl std::fmt::Debug for Material {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Material")
+            .field("bsdf", &self.bsdf)
+            .field("albedo", &self.albedo)
+            .field("albedo_texture", &self.albedo_texture.is_some())
+            .field("bump_texture", &self.bump_texture.is_some())
+            .field("bump_scale", &self.bump_scale)
+            .field("emission", &self.emission)
+            .finish()
+    }
+}
+
+fn reflect(direction: Vec3, normal: Vec3) -> Vec3 {
+    direction - 2. * direction.dot(normal) * normal
+}
+
+fn refract(direction: Vec3, normal: Vec3, eta: f32, cos_theta: f32) -> Vec3 {
+    let perpendicular = eta * (direction + cos_theta * normal);
+    let parallel = -(1. - perpendicular.length_squared()).abs().sqrt() * normal;
+    (perpendicular + parallel).normalize()
+}
+
+// Schlick's approximation of the Fresnel reflectance
+fn schlick(cos_theta: f32, eta: f32) -> f32 {
+    let r0 = ((1. - eta) / (1. + eta)).powi(2);
+    r0 + (1. - r0) * (1. - cos_theta).powi(5)
+}
+
+fn random_in_unit_sphere(sampler: &mut Sampler) -> Vec3 {
+    loop {
+        let point = Vec3::new(sampler.next_f32(), sampler.next_f32(), sampler.next_f32()) * 2. - Vec3::ONE;
+        if point.length_squared() < 1. {
+            return point;
+        }
+    }
+}
@@ -3,79 +3,118 @@ use std::sync::RwLock;
 
 use glam::Vec3;
 
-use crate::polygon::Vertex;
 use crate::polygon::Triangle;
 use crate::ray::{ Ray, IntersectionResult };
-use crate::screen::ScreenBuffersPixel;
+use crate::material::Material;
 use crate::aabb::AABB;
 use crate::aabb::bvh::*;
+use crate::importer::ImportError;
 // use super::*;
 
+type LoadFn = dyn Fn() -> Result<Vec<Object>, ImportError> + Send + Sync;
+
+// Loads again the objects that came from the same file, hot reload replaces all of them with the result.
+// Objects share one reloader when a file produced several of them
+#[derive(Clone)]
+pub struct Reloader(Arc<LoadFn>);
+
+impl Reloader {
+    pub fn new(load: impl Fn() -> Result<Vec<Object>, ImportError> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(load))
+    }
+
+    pub fn load(&self) -> Result<Vec<Object>, ImportError> {
+        (self.0)()
+    }
+
+    pub fn same(&self, other: &Reloader) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl std::fmt::Debug for Reloader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Reloader")
+    }
+}
+
 #[derive(Debug)]
 pub struct Object {
+    pub name: String, // Group or file name from the importer, empty for objects built in code
     pub origin: Vec3,
     #[allow(dead_code)] // will be used in future
     pub rotation: Vec3,
     pub triangles: Vec<Arc<RwLock<Triangle>>>,
+    pub materials: Vec<Material>, // Indexed by Triangle::material
     pub aabb: AABB,
     pub bvh: BVH,
+    pub files: Vec<String>, // Files the object was read from, textures are tracked by their materials
+    pub reloader: Option<Reloader>, // None for objects built in code, they can't be hot reloaded
 }
 
 impl Object {
     pub fn new(triangles: Vec<Triangle>) -> Self {
-        let vertices: Vec<&Vertex> = triangles.iter().flat_map(|tri| {
-            tri.vertices.iter().map(|vertex| {
-                vertex
-            })
-        }).collect();
-        let tris: Vec<Arc<RwLock<Triangle>>> = triangles.iter().map(|tri| {
-            Arc::new(RwLock::new(tri.clone()))
+        let tris: Vec<Arc<RwLock<Triangle>>> = triangles.into_iter().map(|tri| {
+            Arc::new(RwLock::new(tri))
         }).collect();
-        let aabb = AABB::new_from_tri(&triangles); 
-        let bvh = BVH::generate_bottom(&tris);
-        let obj = Object {
+        let bvh = BVH::build(&tris);
+        Self::with_bvh(tris, bvh)
+    }
+
+    // For triangles that already have a BVH, e.g. one read from a cache
+    pub fn with_bvh(triangles: Vec<Arc<RwLock<Triangle>>>, bvh: BVH) -> Self {
+        let aabb = bvh.nodes.first().map_or(AABB::empty(), |root| root.aabb.clone());
+        Object {
+            name: String::new(),
             origin: Vec3::new(0., 0., 0.),
             rotation: Vec3::new(0., 0., 0.,),
-            triangles: tris,
+            triangles,
+            materials: vec![Material::default()],
             aabb,
             bvh,
-        };
-        return obj;
+            files: Vec::new(),
+            reloader: None,
+        }
     }
 
+    // Object files and the textures its materials were read from
+    pub fn watched_files(&self) -> Vec<String> {
+        let textures = self.materials.iter().flat_map(|material| [&material.albedo_texture, &material.bump_texture]);
+        let texture_files = textures.filter_map(|texture| texture.as_ref().and_then(|texture| texture.path.clone()));
+        self.files.iter().cloned().chain(texture_files).collect()
+    }
+
+    // The BVH grows in place, call rebuild_bvh after adding a lot of triangles if tracing gets slower
+    #[allow(dead_code)]
     pub fn append_triangle(&mut self, triangle: Triangle) {
+        self.aabb = triangle.vertices.iter().fold(self.aabb.clone(), |aabb, vertex| aabb.include_vertex(vertex.clone()));
         self.triangles.push(Arc::new(RwLock::new(triangle)));
+        self.bvh.insert(&self.triangles, self.triangles.len() - 1);
     }
 
+    #[allow(dead_code)]
     pub fn append_triangle_array(&mut self, triangles: &[Triangle]) {
         triangles.iter().for_each(|tri| {
             self.append_triangle(tri.clone());
         });
     }
 
-    pub fn calculate_intersection(&self, ray: &Ray, pixel_pointer: *mut ScreenBuffersPixel) -> Option<IntersectionResult> {
+    // Call after editing triangles in place, bounds are updated but the BVH tree is kept
+    pub fn refit(&mut self) {
+        self.bvh.refit(&self.triangles);
+        self.aabb = self.bvh.nodes.first().map_or(AABB::empty(), |root| root.aabb.clone());
+    }
+
+    // Full rebuild, for when edits changed the mesh so much that refitting made tracing slow
+    #[allow(dead_code)]
+    pub fn rebuild_bvh(&mut self) {
+        self.bvh = BVH::build(&self.triangles);
+        self.aabb = self.bvh.nodes.first().map_or(AABB::empty(), |root| root.aabb.clone());
+    }
+
+    pub fn calculate_intersection(&self, ray: &Ray) -> Option<IntersectionResult> {
         if !self.ray_aabb(ray) { return None; }
-        let mut closest_intersection: Option<IntersectionResult> = None;
-
-        let bvh_tris = self.traverse_bvh(ray);
-
-        // self.triangles.iter().for_each(|triangle| {
-        bvh_tris.iter().for_each(|triangle| {
-        unsafe {
-            if let Some(current_intersection) = triangle.read().unwrap_unchecked().intersects_ray(&self.origin, &ray, pixel_pointer) {
-                if closest_intersection.is_none() {
-                    closest_intersection = Some(current_intersection);
-                } else {
-                    if current_intersection.distance < closest_intersection.unwrap().distance {
-                        closest_intersection = Some(current_intersection);
-                    }
-                }
-            }
-                unsafe {
-                    (*pixel_pointer).rendered = 0xFFFFFFFFu32;
-                }
-        }});
-        return closest_intersection;
+        self.bvh.closest_hit(&self.triangles, self.origin, ray)
     }
 
     pub fn ray_aabb(&self, ray: &Ray) -> bool {
@@ -103,65 +142,4 @@ impl Object {
             return false;
         }
     }
-
-    pub fn traverse_bvh(&self, ray: &Ray) -> Vec<Arc<RwLock<Triangle>>> {
-        let mut tris = Vec::new();
-        if self.ray_any_aabb(ray, &self.bvh.aabb) {
-            self.traverse_bvh_2(&self.bvh, ray, &mut tris);
-        }
-        return tris;
-    }
-
-    pub fn traverse_bvh_2<'a>(&self, bvh: &'a BVH, ray: &Ray, tris: &mut Vec<Arc<RwLock<Triangle>>>) {
-        match &bvh.left {
-            BVHNode::Leaf(data) => tris.push(data.clone()),
-            BVHNode::Node(data) => {
-                if self.ray_any_aabb(ray, &data.aabb) {
-                    self.traverse_bvh_2(data, ray, tris);
-                }
-            },
-        };
-        match &bvh.right {
-            BVHNode::Leaf(data) => tris.push(data.clone()),
-            BVHNode::Node(data) => {
-                if self.ray_any_aabb(ray, &data.aabb) {
-                    self.traverse_bvh_2(data, ray, tris);
-                }
-            },
-        };
-    }
-
-    pub fn debug_count_repeated_triangles(&self) {
-        let d = self.debug_get_bvh_end(self.bvh.clone());
-        let mut c = 0;
-        for a in &d {
-            for b in &d {
-                if (a as *const _) == (b as *const _) {
-                    c += 1;
-                }
-            }
-        }
-        println!("in total {c} same tris (632 for teapot is ideal)");
-        println!("total total tris are {:?}", self.debug_get_bvh_end(self.bvh.clone()).len());
-        if let BVHNode::Node(left) = &self.bvh.left {
-            if let BVHNode::Node(right) = &self.bvh.left {
-                println!("left length: {:?}", self.debug_get_bvh_end(*left.clone()).len());
-                println!("right length: {:?}", self.debug_get_bvh_end(*right.clone()).len());
-                println!("left:\t{:?}\nright:\t{:?}", left.aabb, right.aabb);
-            }
-        }
-    }
-
-    pub fn debug_get_bvh_end(&self, bvh: BVH) -> Vec<Arc<RwLock<Triangle>>> {
-        let mut tris = Vec::new();
-        match &bvh.left {
-            BVHNode::Leaf(data) => tris.push(data.clone()),
-            BVHNode::Node(data) => tris.extend(self.debug_get_bvh_end(*data.clone())),
-        };
-        match &bvh.right {
-            BVHNode::Leaf(data) => tris.push(data.clone()),
-            BVHNode::Node(data) => tris.extend(self.debug_get_bvh_end(*data.clone())),
-        };
-        return tris;
-    }
 }
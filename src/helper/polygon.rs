@@ -1,22 +1,26 @@
 use glam::{Vec2, Vec3};
+use derive_new::new;
 
-use crate::helper::ARGB4_to_ARGBu32;
 use crate::ray::{ Ray, IntersectionResult };
-use crate::screen::ScreenBuffersPixel;
 
-#[derive(Clone, Debug)]
+#[derive(new, Clone, Debug)]
 pub struct Vertex {
     pub pos: Vec3,
     pub uv: Vec2,
+    #[new(value = "None")]
+    pub normal: Option<Vec3>, // Used for smooth shading when all vertices of a triangle have one
+    #[new(value = "Vec3::ONE")]
+    pub color: Vec3, // Linear, multiplied with material albedo
 }
 
 #[derive(Clone, Debug)]
 pub struct Triangle {
     pub vertices: [Vertex; 3],
+    pub material: usize, // Index into the owning Object's materials
 }
 
 impl Triangle {
-    pub fn intersects_ray(&self, origin: &Vec3, ray: &Ray, pixel_pointer: *mut ScreenBuffersPixel) -> Option<IntersectionResult> {
+    pub fn intersects_ray(&self, origin: &Vec3, ray: &Ray) -> Option<IntersectionResult> {
         let epsilon = f32::EPSILON;
         let edge1 = (origin+self.vertices[1].pos) - (origin+self.vertices[0].pos);
         let edge2 = (origin+self.vertices[2].pos) - (origin+self.vertices[0].pos);
@@ -31,7 +35,7 @@ impl Triangle {
         let s = ray.origin - (origin+self.vertices[0].pos);
         let u = f * s.dot(h);
 
-        if u < 0.0 || u > 1.0 {
+        if !(0.0..=1.0).contains(&u) {
             return None;
         }
 
@@ -44,34 +48,89 @@ impl Triangle {
 
         let t = f * edge2.dot(q);
 
-        if t > epsilon {
-            let intersection = IntersectionResult { distance: t, uv: ray.origin + ray.direction * t };
-            if t < unsafe { *pixel_pointer }.alpha {
-                unsafe { (*pixel_pointer).alpha = t };
-
-                let w = 1.-u-v;
-                let texU = (w*self.vertices[0].uv[0] + u*self.vertices[1].uv[0] + v*self.vertices[2].uv[0]).rem_euclid(255.);
-                let texV = (w*self.vertices[0].uv[1] + u*self.vertices[1].uv[1] + v*self.vertices[2].uv[1]).rem_euclid(255.);
-
-                // TODO: Proper texture rendering
-                const IMG: &[u8; 786432] = include_bytes!("../../test/test_image.raw");
-                let texX = (texU*512.).floor().rem_euclid(512.) as usize;
-                let texY = (texV*-512.).floor().rem_euclid(512.) as usize;
-                let r = IMG[texX*3 + texY*512*3];
-                let g = IMG[texX*3 + texY*512*3 + 1];
-                let b = IMG[texX*3 + texY*512*3 + 2];
-
-                unsafe { (*pixel_pointer).rendered = ARGB4_to_ARGBu32(0xFF, r, g, b) };
-                return Some(intersection);
-            } else {
-                return None;
-            }
-        } else {
+        if t <= epsilon {
             return None;
         }
+
+        let w = 1.-u-v;
+        let normal = edge1.cross(edge2).normalize();
+        let shading_normal = match (self.vertices[0].normal, self.vertices[1].normal, self.vertices[2].normal) {
+            (Some(n0), Some(n1), Some(n2)) => (w*n0 + u*n1 + v*n2).try_normalize().unwrap_or(normal),
+            _ => normal,
+        };
+        // Surface derivatives along u and v, used to orient bump maps
+        let (uv0, uv1, uv2) = (self.vertices[0].uv, self.vertices[1].uv, self.vertices[2].uv);
+        let (duv1, duv2) = (uv1 - uv0, uv2 - uv0);
+        let determinant = duv1.x*duv2.y - duv1.y*duv2.x;
+        let (tangent, bitangent) = if determinant.abs() > f32::EPSILON {
+            ((edge1*duv2.y - edge2*duv1.y) / determinant, (edge2*duv1.x - edge1*duv2.x) / determinant)
+        } else {
+            normal.any_orthonormal_pair()
+        };
+
+        Some(IntersectionResult {
+            distance: t,
+            position: ray.at(t),
+            normal,
+            shading_normal,
+            tangent,
+            bitangent,
+            uv: w*self.vertices[0].uv + u*self.vertices[1].uv + v*self.vertices[2].uv,
+            color: w*self.vertices[0].color + u*self.vertices[1].color + v*self.vertices[2].color,
+            material: self.material,
+        })
     }
 
     pub fn center(&self) -> Vec3 {
         (self.vertices[0].pos + self.vertices[1].pos + self.vertices[2].pos)/3.
     }
 }
+
+// Splits a planar polygon into triangles by ear clipping, keeping its winding.
+// Returns indices into `polygon`. Falls back to a fan when the polygon is degenerate or self intersecting
+pub fn triangulate(polygon: &[Vec3]) -> Vec<[usize; 3]> {
+    let n = polygon.len();
+    if n < 3 {
+        return Vec::new();
+    }
+    if n == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Newell's method gives a stable normal even for concave polygons
+    let mut normal = Vec3::ZERO;
+    for i in 0..n {
+        let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+        normal += Vec3::new((a.y - b.y) * (a.z + b.z), (a.z - b.z) * (a.x + b.x), (a.x - b.x) * (a.y + b.y));
+    }
+
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut triangles = Vec::with_capacity(n - 2);
+    if normal.length_squared() > 0. {
+        while remaining.len() > 3 {
+            let m = remaining.len();
+            let ear = (0..m).find(|&i| {
+                let (prev, current, next) = (remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]);
+                let (a, b, c) = (polygon[prev], polygon[current], polygon[next]);
+                let convex = (b - a).cross(c - b).dot(normal) > 0.;
+                convex && !remaining.iter().any(|&j| {
+                    j != prev && j != current && j != next && point_in_triangle(polygon[j], a, b, c, normal)
+                })
+            });
+            let Some(i) = ear else { break };
+            triangles.push([remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]]);
+            remaining.remove(i);
+        }
+    }
+
+    for i in 1..remaining.len() - 1 {
+        triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+    }
+    triangles
+}
+
+fn point_in_triangle(point: Vec3, a: Vec3, b: Vec3, c: Vec3, normal: Vec3) -> bool {
+    (b - a).cross(point - a).dot(normal) >= 0. &&
+    (c - b).cross(point - b).dot(normal) >= 0. &&
+    (a - c).cross(point - c).dot(normal) >= 0.
+}
@@ -1,9 +1,16 @@
-use glam::Vec3;
+use glam::{ Vec2, Vec3 };
 
 #[derive(Clone, Copy)]
 pub struct IntersectionResult {
     pub distance: f32,
-    pub uv: Vec3, // FIXME: this is not uv but well ok I guess...
+    pub position: Vec3,
+    pub normal: Vec3, // Geometric normal, not flipped towards the ray
+    pub shading_normal: Vec3, // Interpolated vertex normal, same as normal for flat triangles
+    pub tangent: Vec3, // Position derivative along u
+    pub bitangent: Vec3, // Position derivative along v
+    pub uv: Vec2,
+    pub color: Vec3, // Interpolated vertex color
+    pub material: usize,
 }
 
 #[derive(Clone, Copy)]
@@ -15,8 +22,12 @@ pub struct Ray {
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Ray {
         return Ray {
-            origin: origin,
+            origin,
             direction: direction.normalize()
         };
     }
+
+    pub fn at(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
 }
@@ -0,0 +1,58 @@
+use glam::{ Vec2, Vec3 };
+
+// Small PCG32 generator. Every pixel gets its own instance so rendering stays lock free
+#[derive(Clone)]
+pub struct Sampler {
+    state: u64,
+}
+
+impl Sampler {
+    pub fn new(pixel: u64, frame: u64) -> Self {
+        let mut sampler = Self { state: 0 };
+        sampler.next_u32();
+        sampler.state = sampler.state.wrapping_add(pixel.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ frame.wrapping_mul(0xD1B5_4A32_D192_ED03));
+        sampler.next_u32();
+        sampler
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    // Uniform in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    pub fn next_vec2(&mut self) -> Vec2 {
+        Vec2::new(self.next_f32(), self.next_f32())
+    }
+}
+
+// Cosine weighted direction around `normal`, pdf is cos(theta)/PI
+pub fn cosine_hemisphere(normal: Vec3, sample: Vec2) -> Vec3 {
+    let r = sample.x.sqrt();
+    let phi = 2. * std::f32::consts::PI * sample.y;
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+    let local_z = (1. - sample.x).max(0.).sqrt();
+    (tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * local_z).normalize()
+}
+
+// Maps the unit square onto the unit disk keeping samples evenly spread
+pub fn concentric_disk(sample: Vec2) -> Vec2 {
+    let offset = sample * 2. - Vec2::ONE;
+    if offset == Vec2::ZERO {
+        return Vec2::ZERO;
+    }
+    let quarter_pi = std::f32::consts::FRAC_PI_4;
+    let (radius, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, quarter_pi * (offset.y / offset.x))
+    } else {
+        (offset.y, 2. * quarter_pi - quarter_pi * (offset.x / offset.y))
+    };
+    Vec2::new(theta.cos(), theta.sin()) * radius
+}
@@ -1,24 +1,79 @@
+use derive_new::new;
+
 use crate::texture::Texture;
-use crate::screen::ScreenBuffers;
+use crate::screen::{ ScreenBuffers, AccumulationBuffer };
+use crate::integrator::PathTracer;
 use crate::object::Object;
+use crate::aabb::tlas::{ TLAS, Geometry };
 use crate::camera::Camera;
+use crate::importer::{ Importer, ImportError };
+
+// How a scene wants to be rendered, scene files can set all of these
+#[derive(new, Clone)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    #[new(value = "64")]
+    pub spp: u32, // Frames accumulated by offline renders
+    #[new(value = "PathTracer::new()")]
+    pub tracer: PathTracer,
+}
 
 pub struct Scene {
     pub screen: ScreenBuffers,
+    pub accumulation: AccumulationBuffer,
     pub objects: Vec<Object>,
+    pub tlas: TLAS, // Over objects, kept up to date by render_frame
     pub camera: Camera,
+    pub settings: RenderSettings,
+    pub source: Option<String>, // Scene file it was loaded from, watched for hot reload
 }
 
 impl Scene {
-    pub fn render_to_texture(&mut self, camera: &Camera, texture: &Texture) {
-        texture.get_pixel_iterator().enumerate().for_each(|(i, pixel)| {
-            let x = i % texture.size_x();
-            let y = i / texture.size_x();
-
-            let mut p = pixel.write().unwrap();
-            p.r_set(x.min(255) as u8);
-            p.g_set(y.min(255) as u8);
-            drop(p);
-        });
+    pub fn new(objects: Vec<Object>, camera: Camera, width: usize, height: usize) -> Self {
+        Self {
+            screen: ScreenBuffers::new(width, height),
+            accumulation: AccumulationBuffer::new(width, height),
+            tlas: TLAS::build(&objects),
+            objects,
+            camera,
+            settings: RenderSettings::new(width, height),
+            source: None,
+        }
+    }
+
+    // Reads a scene description file, see Importer::scene for the format
+    pub fn load(file_path: &str) -> Result<Self, ImportError> {
+        let mut scene = Importer::scene(file_path)?;
+        scene.source = Some(file_path.to_string());
+        Ok(scene)
+    }
+
+    // Changes render resolution, accumulated samples are discarded
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.screen = ScreenBuffers::new(width, height);
+        self.accumulation = AccumulationBuffer::new(width, height);
+    }
+
+    // Renders one more frame into the accumulation buffer, starting over if anything moved
+    pub fn render_frame(&mut self, tracer: &PathTracer, frame: u64) {
+        self.accumulation.reset_if_changed(&self.camera, &self.objects);
+        self.screen.clear();
+        self.tlas.update(&self.objects);
+        self.camera.render(Geometry { objects: &self.objects, tlas: &self.tlas }, tracer, &mut self.screen, frame);
+        self.accumulation.accumulate(&mut self.screen);
+    }
+
+    // Offline render of the scene from any camera at the texture's resolution
+    pub fn render_to_texture(&self, camera: &Camera, texture: &Texture, tracer: &PathTracer, supersampling: u32) {
+        // Objects may have moved since the last frame, the scene can't be updated from here
+        let rebuilt;
+        let tlas = if self.tlas.is_current(&self.objects) {
+            &self.tlas
+        } else {
+            rebuilt = TLAS::build(&self.objects);
+            &rebuilt
+        };
+        camera.render_to_texture(Geometry { objects: &self.objects, tlas }, tracer, texture, supersampling);
     }
 }
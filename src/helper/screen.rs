@@ -1,6 +1,10 @@
 use derive_new::new;
+use glam::Vec3;
 
-use crate::helper::ARGB4_to_ARGBu32;
+use crate::helper::{ ARGB4_to_ARGBu32, linear_to_ARGBu32 };
+use crate::camera::Camera;
+use crate::object::Object;
+use crate::texture::Texture;
 
 #[derive(new, Clone, Copy)]
 pub struct ScreenBuffersPixel {
@@ -8,9 +12,13 @@ pub struct ScreenBuffersPixel {
     pub rendered: u32,
     #[new(value = "f32::INFINITY")]
     pub alpha: f32,
+    #[new(value = "Vec3::ZERO")]
+    pub color: Vec3, // Linear radiance of the last rendered frame
 }
 
 pub struct ScreenBuffers {
+    width: usize,
+    height: usize,
     pub pixels: Vec<ScreenBuffersPixel>,
 }
 
@@ -18,10 +26,20 @@ impl ScreenBuffers {
     pub fn new(width: usize, height: usize) -> Self {
         let size = width*height;
         Self {
+            width,
+            height,
             pixels: vec![ScreenBuffersPixel::new(); size]
         }
     }
 
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
     pub fn clear(&mut self) {
         self.fill(ScreenBuffersPixel::new());
     }
@@ -30,6 +48,14 @@ impl ScreenBuffers {
         return self.pixels.iter().map(|pixel| {pixel.rendered}).collect();
     }
 
+    pub fn to_texture(&self) -> Texture {
+        let texture = Texture::new(self.width, self.height);
+        texture.get_pixel_iterator().zip(self.pixels.iter()).for_each(|(pixel, screen_pixel)| {
+            pixel.write().unwrap().argb_set(screen_pixel.rendered);
+        });
+        texture
+    }
+
     pub fn get_depth(&self) -> Vec<u32> {
         let depth: Vec<u8> = self.pixels.iter().map(|pixel| {((-pixel.alpha)+255.) as u8}).collect();
         let mut result = Vec::<u32>::with_capacity(depth.len());
@@ -43,3 +69,54 @@ impl ScreenBuffers {
         self.pixels.fill(value);
     }
 }
+
+// HDR sum of every frame rendered since the camera or any object last moved
+pub struct AccumulationBuffer {
+    samples: Vec<Vec3>,
+    frames: u32,
+    last_camera: Option<Camera>,
+    last_origins: Vec<Vec3>,
+}
+
+impl AccumulationBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            samples: vec![Vec3::ZERO; width*height],
+            frames: 0,
+            last_camera: None,
+            last_origins: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.samples.fill(Vec3::ZERO);
+        self.frames = 0;
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    // Resets accumulated samples when the view they were rendered from is no longer valid
+    pub fn reset_if_changed(&mut self, camera: &Camera, objects: &[Object]) {
+        let camera_changed = self.last_camera.as_ref() != Some(camera);
+        let objects_changed = self.last_origins.len() != objects.len()
+            || self.last_origins.iter().zip(objects).any(|(origin, object)| *origin != object.origin);
+
+        if camera_changed || objects_changed {
+            self.reset();
+            self.last_camera = Some(camera.clone());
+            self.last_origins = objects.iter().map(|object| object.origin).collect();
+        }
+    }
+
+    // Adds the last frame of `screen` and replaces its rendered colors with the running average
+    pub fn accumulate(&mut self, screen: &mut ScreenBuffers) {
+        self.frames += 1;
+        let inv_frames = 1. / self.frames as f32;
+        self.samples.iter_mut().zip(screen.pixels.iter_mut()).for_each(|(sum, pixel)| {
+            *sum += pixel.color;
+            pixel.rendered = linear_to_ARGBu32(*sum * inv_frames);
+        });
+    }
+}
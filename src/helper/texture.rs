@@ -4,12 +4,18 @@ use std::sync::RwLock;
 use std::io::Write;
 use std::slice::Iter;
 
+use glam::{ Vec2, Vec3 };
+use rayon::prelude::*;
+
 use pixel::Pixel;
 
+use crate::importer::ImportError;
+
 pub struct Texture {
     size_x: usize,
     size_y: usize,
     data: Vec<RwLock<Pixel>>,
+    pub path: Option<String>, // File the texture was read from, watched for hot reload
 }
 
 impl Texture {
@@ -18,11 +24,45 @@ impl Texture {
         for _ in 0..(x*y) {
             empty_vector.push(RwLock::new(Pixel::new()));
         }
-        Texture { size_x: x, size_y: y, data: empty_vector }
+        Texture { size_x: x, size_y: y, data: empty_vector, path: None }
     }
 
-    pub fn new_from_file(file_path: String) -> Texture {
-        unimplemented!()
+    // Reads PNG and JPEG images as well as binary (P6) or ASCII (P3) PPM, the format save_to_file writes.
+    // Alpha is dropped
+    pub fn new_from_file(file_path: &str) -> Result<Texture, ImportError> {
+        let error = |reason: &str| ImportError::new(file_path, None, reason);
+        let bytes = std::fs::read(file_path).map_err(|e| error(&format!("Cannot open file: {}", e)))?;
+
+        let (size_x, size_y, channels) = if bytes.starts_with(b"P3") || bytes.starts_with(b"P6") {
+            read_ppm(&bytes).map_err(|reason| error(&reason))?
+        } else {
+            let image = image::load_from_memory(&bytes)
+                .map_err(|e| error(&format!("Unsupported image, only PNG, JPEG and PPM textures are supported: {}", e)))?;
+            (image.width() as usize, image.height() as usize, image.into_rgb8().into_raw())
+        };
+        if size_x == 0 || size_y == 0 {
+            return Err(error("Image has no pixels"));
+        }
+        if channels.len() < size_x*size_y*3 {
+            return Err(error("Not enough pixel data"));
+        }
+
+        let mut texture = Texture::new_from_rgb(size_x, size_y, &channels);
+        texture.path = Some(file_path.to_string());
+        Ok(texture)
+    }
+
+    // Opaque texture from tightly packed 8 bit RGB rows, top row first
+    pub fn new_from_rgb(x: usize, y: usize, channels: &[u8]) -> Texture {
+        let texture = Texture::new(x, y);
+        texture.get_pixel_iterator().zip(channels.chunks_exact(3)).for_each(|(pixel, rgb)| {
+            let mut p = pixel.write().unwrap();
+            p.a_set(0xFF);
+            p.r_set(rgb[0]);
+            p.g_set(rgb[1]);
+            p.b_set(rgb[2]);
+        });
+        texture
     }
 
     pub fn get_pixel(&self, x: usize, y: usize) -> &RwLock<Pixel> {
@@ -44,10 +84,26 @@ impl Texture {
         return self.get_pixel(pixel_x, pixel_y);
     }
 
+    // Linear color at uv, texture is assumed to be stored in sRGB
+    pub fn sample(&self, uv: Vec2) -> Vec3 {
+        let pixel = self.get_pixel_relative(uv.x, uv.y).read().unwrap();
+        (Vec3::new(pixel.r() as f32, pixel.g() as f32, pixel.b() as f32) / 255.).powf(2.2)
+    }
+
+    // Brightness at uv in 0..1 without gamma correction, for height maps
+    pub fn sample_height(&self, uv: Vec2) -> f32 {
+        let pixel = self.get_pixel_relative(uv.x, uv.y).read().unwrap();
+        (pixel.r() as f32 + pixel.g() as f32 + pixel.b() as f32) / (3. * 255.)
+    }
+
     pub fn get_pixel_iterator(&self) -> Iter<RwLock<Pixel>> {
         return self.data.iter();
     }
 
+    pub fn get_pixel_par_iterator(&self) -> rayon::slice::Iter<'_, RwLock<Pixel>> {
+        self.data.par_iter()
+    }
+
     pub fn size_x(&self) -> usize {
         return self.size_x;
     }
@@ -57,14 +113,61 @@ impl Texture {
     }
 
     pub fn save_to_file(&self) {
-        use std::fs::File;
         use std::time::{SystemTime, UNIX_EPOCH};
         let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        let mut file = File::create(format!("{now}.ppm")).unwrap();
-        file.write_all(format!("P3\n{} {}\n255\n", self.size_x, self.size_y).as_bytes()).unwrap();
+        self.save_to_path(&format!("{now}.ppm")).unwrap();
+    }
+
+    // Writes an ASCII (P3) PPM image
+    pub fn save_to_path(&self, file_path: &str) -> std::io::Result<()> {
+        use std::fs::File;
+        use std::io::BufWriter;
+        let mut file = BufWriter::new(File::create(file_path)?);
+        file.write_all(format!("P3\n{} {}\n255\n", self.size_x, self.size_y).as_bytes())?;
         for pix in self.get_pixel_iterator() {
             let unwrapped = pix.read().unwrap();
-            file.write_all(format!("{} {} {}\n", unwrapped.r(), unwrapped.g(), unwrapped.b()).as_bytes()).unwrap();
+            file.write_all(format!("{} {} {}\n", unwrapped.r(), unwrapped.g(), unwrapped.b()).as_bytes())?;
         };
+        file.flush()
     }
 }
+
+// Width, height and 8 bit RGB values of a P3 or P6 image
+fn read_ppm(bytes: &[u8]) -> Result<(usize, usize, Vec<u8>), String> {
+    // Header is 4 whitespace separated tokens, comments start with '#' and run to the end of line
+    let mut header = Vec::new();
+    let mut position = 0;
+    while header.len() < 4 && position < bytes.len() {
+        match bytes[position] {
+            b'#' => while position < bytes.len() && bytes[position] != b'\n' { position += 1 },
+            byte if byte.is_ascii_whitespace() => position += 1,
+            _ => {
+                let start = position;
+                while position < bytes.len() && !bytes[position].is_ascii_whitespace() { position += 1 }
+                header.push(String::from_utf8_lossy(&bytes[start..position]).to_string());
+            },
+        }
+    }
+    if header.len() != 4 {
+        return Err("Incomplete PPM header".to_string());
+    }
+    let size_x: usize = header[1].parse().map_err(|_| "Invalid PPM width")?;
+    let size_y: usize = header[2].parse().map_err(|_| "Invalid PPM height")?;
+    let max_value: u32 = header[3].parse().map_err(|_| "Invalid PPM max value")?;
+    if max_value == 0 || max_value > 255 {
+        return Err("Only 8 bit PPM images are supported".to_string());
+    }
+
+    // Values are stretched to 0..255 whatever the max value, anything above it is clamped
+    let scale = |value: u32| (value.min(max_value) * 255 / max_value) as u8;
+    let channels = match header[0].as_str() {
+        // Exactly one whitespace byte separates header and data
+        "P6" => bytes.get(position+1..).unwrap_or_default().iter().map(|&value| scale(value as u32)).collect(),
+        _ => String::from_utf8_lossy(&bytes[position..])
+            .split_whitespace()
+            .map(|value| value.parse::<u32>().map(scale))
+            .collect::<Result<_, _>>()
+            .map_err(|_| "Invalid PPM value")?,
+    };
+    Ok((size_x, size_y, channels))
+}
@@ -28,6 +28,12 @@ impl Pixel {
         self.number = value;
     }
 
+    // Sets channels from a 0xAARRGGBB value. Unlike u32_set this doesn't depend on the platform byte order
+    pub fn argb_set(&mut self, value: u32) {
+        let [a, r, g, b] = value.to_be_bytes();
+        self.color = (a, r, g, b);
+    }
+
     pub fn a(&self) -> u8 {
         let result = unsafe{ self.color.0 };
         return result;
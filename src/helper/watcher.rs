@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::time::{ Duration, Instant, SystemTime };
+
+use crate::object::{ Object, Reloader };
+use crate::scene::Scene;
+
+// Checking every file each frame would cost more than the edits are worth
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Polls modification times of every file the scene was loaded from. Changed meshes and textures reload only the
+// objects using them, a changed scene file reloads everything. The camera is kept either way
+pub struct Watcher {
+    mtimes: HashMap<String, Option<SystemTime>>, // None while a file is missing, editors often replace files on save
+    last_poll: Instant,
+}
+
+impl Watcher {
+    pub fn new(scene: &Scene) -> Self {
+        let mut watcher = Self { mtimes: HashMap::new(), last_poll: Instant::now() };
+        watcher.snapshot(scene);
+        watcher
+    }
+
+    // Returns true when the scene was changed
+    pub fn poll(&mut self, scene: &mut Scene) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        let changed: Vec<&String> = self.mtimes.iter().filter(|(path, mtime)| modified(path) != **mtime).map(|(path, _)| path).collect();
+        if changed.is_empty() {
+            return false;
+        }
+        let scene_changed = scene.source.as_ref().is_some_and(|source| changed.contains(&source));
+        let reloaded = if scene_changed {
+            reload_scene(scene)
+        } else {
+            reload_objects(scene, &changed)
+        };
+
+        // Failed reloads keep the old objects and are tried again on the next save
+        self.snapshot(scene);
+        if reloaded {
+            scene.accumulation.reset();
+        }
+        reloaded
+    }
+
+    // Objects without a reloader can't be reloaded, their files aren't watched
+    fn snapshot(&mut self, scene: &Scene) {
+        let reloadable = scene.objects.iter().filter(|object| object.reloader.is_some());
+        let files = scene.source.iter().cloned().chain(reloadable.flat_map(|object| object.watched_files()));
+        self.mtimes = files.map(|file| { let mtime = modified(&file); (file, mtime) }).collect();
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn reload_scene(scene: &mut Scene) -> bool {
+    let Some(source) = scene.source.clone() else { return false };
+    match Scene::load(&source) {
+        Ok(loaded) => {
+            scene.objects = loaded.objects;
+            scene.settings = loaded.settings;
+            println!("Reloaded scene {}", source);
+            true
+        },
+        Err(e) => {
+            eprintln!("Cannot reload scene: {}", e);
+            false
+        },
+    }
+}
+
+// Objects loaded together share a reloader and are replaced together, new objects take the place of the old ones
+fn reload_objects(scene: &mut Scene, changed: &[&String]) -> bool {
+    let mut reloaders: Vec<Reloader> = Vec::new();
+    for object in &scene.objects {
+        let Some(reloader) = &object.reloader else { continue };
+        let uses_changed = object.watched_files().iter().any(|file| changed.contains(&file));
+        if uses_changed && !reloaders.iter().any(|other| other.same(reloader)) {
+            reloaders.push(reloader.clone());
+        }
+    }
+
+    let mut reloaded = false;
+    for reloader in reloaders {
+        match reloader.load() {
+            Ok(objects) => {
+                let loaded_by = |object: &Object| object.reloader.as_ref().is_some_and(|other| other.same(&reloader));
+                let Some(position) = scene.objects.iter().position(loaded_by) else { continue };
+                let names: Vec<&str> = objects.iter().map(|object| object.name.as_str()).collect();
+                println!("Reloaded {}", names.join(", "));
+                scene.objects.retain(|object| !loaded_by(object));
+                scene.objects.splice(position..position, objects);
+                reloaded = true;
+            },
+            Err(e) => eprintln!("Cannot reload: {}", e),
+        }
+    }
+    reloaded
+}
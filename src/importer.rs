@@ -1,52 +1,96 @@
-use glam::{Vec2, Vec3};
+pub mod obj;
+pub mod mtl;
+pub mod gltf;
+pub mod stl;
+pub mod ply;
+pub mod scene;
+mod cache;
 
-use crate::object::Object;
-use crate::polygon::{ Vertex, Triangle };
+use std::fmt;
+use std::path::Path;
+
+use glam::Vec3;
+
+use crate::object::{ Object, Reloader };
+use crate::camera::Camera;
+
+// Something that went wrong while reading a file. In lenient mode these are collected as warnings instead
+#[derive(Debug, Clone)]
+pub struct ImportError {
+    pub path: String,
+    pub line: Option<usize>, // 1 based, None when the error isn't tied to a line, e.g. the file is missing
+    pub reason: String,
+}
+
+impl ImportError {
+    pub fn new(path: &str, line: Option<usize>, reason: impl Into<String>) -> Self {
+        Self { path: path.to_string(), line, reason: reason.into() }
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path, line, self.reason),
+            None => write!(f, "{}: {}", self.path, self.reason),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
 
 pub struct Importer{}
 
+// What a single mesh or scene file contributes
+pub struct Imported {
+    pub objects: Vec<Object>,
+    pub camera: Option<Camera>, // Only glTF files carry a camera
+    pub warnings: Vec<ImportError>, // Only lenient OBJ and glTF imports produce warnings
+}
+
 impl Importer {
-    pub fn obj(file_path: &str) -> Object {
-        use std::io::{BufRead, BufReader};
-        use std::fs::File;
-
-        let reader = BufReader::new(File::open(file_path).expect(format!("Cannot open {}", file_path).as_str()));
-
-        let mut vertices: Vec<Vertex> = Vec::new();
-        let mut faces: Vec<Triangle> = Vec::new();
-
-        'line: for line in reader.lines() {
-            let unwrapped = line.unwrap();
-            let mut splitted = unwrapped.split_whitespace();
-            let operation = splitted.next().unwrap_or("#"); // Empty lines are considered comments
-            let words: Vec<&str> = splitted.collect();
-            match operation {
-                "#" => { println!("Comment: '{:?}'", words); continue 'line; },
-                "v" => {
-                    assert_eq!(words.len(), 3, "Non 3D vertices are unsupported");
-                    vertices.push(Vertex {
-                        pos: Vec3::new(
-                            words[0].parse::<f32>().unwrap(),
-                            words[1].parse::<f32>().unwrap(),
-                            words[2].parse::<f32>().unwrap(),
-                        ),
-                        uv: Vec2::new(1., 1.),
-                    });
-                },
-                "f" => {
-                    assert_eq!(words.len(), 3, "Non triangulated faces are unsupported");
-                    faces.push(Triangle { vertices: [
-                        vertices[words[0].parse::<usize>().unwrap() - 1].clone(),
-                        vertices[words[1].parse::<usize>().unwrap() - 1].clone(),
-                        vertices[words[2].parse::<usize>().unwrap() - 1].clone(),
-                    ] });
-                }
-                _ => { println!("Unknown operation '{}'", operation); continue 'line; },
-            }
+    // Picks the importer by extension. OBJ files are split into their groups, lenient only applies to OBJ and glTF
+    // and weld to STL
+    pub fn file(file_path: &str, lenient: bool, weld: bool) -> Result<Imported, ImportError> {
+        let extension = Path::new(file_path).extension().map(|extension| extension.to_string_lossy().to_lowercase());
+        let mut imported = Imported { objects: Vec::new(), camera: None, warnings: Vec::new() };
+        match extension.as_deref() {
+            Some("gltf") | Some("glb") => {
+                let scene = Self::gltf(file_path, lenient)?;
+                imported.objects = scene.objects;
+                imported.camera = scene.camera;
+                imported.warnings = scene.warnings;
+            },
+            Some("stl") => imported.objects.push(Self::stl(file_path, weld)?),
+            Some("ply") => imported.objects.push(Self::ply(file_path)?),
+            _ if lenient => (imported.objects, imported.warnings) = Self::obj_groups_lenient(file_path)?,
+            _ => imported.objects = Self::obj_groups(file_path)?,
         }
-        println!("Imported Faces: {}", faces.len());
-
-        let object = Object::new(faces);
-        return object;
+        // Hot reload reads the file again with the same options
+        let path = file_path.to_string();
+        let reloader = Reloader::new(move || Self::file(&path, lenient, weld).map(|imported| imported.objects));
+        for object in &mut imported.objects {
+            object.reloader = Some(reloader.clone());
+        }
+        Ok(imported)
     }
 }
+
+// Shared by the text formats and the command line
+pub fn parse_number<T: std::str::FromStr>(word: &str) -> Result<T, String> {
+    word.parse().map_err(|_| format!("Invalid number '{}'", word))
+}
+
+pub fn parse_floats(words: &[&str]) -> Result<Vec<f32>, String> {
+    words.iter().map(|word| parse_number(word)).collect()
+}
+
+// Name for objects the file doesn't name
+fn file_stem(file_path: &str) -> String {
+    Path::new(file_path).file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned())
+}
+
+// Vertex colors are authored like textures, in sRGB
+fn srgb_to_linear(color: Vec3) -> Vec3 {
+    color.max(Vec3::ZERO).powf(2.2)
+}
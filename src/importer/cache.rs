@@ -0,0 +1,399 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::sync::{ Arc, RwLock };
+
+use glam::{ Vec2, Vec3 };
+
+use crate::aabb::AABB;
+use crate::aabb::bvh::{ BVH, BVHNode, MAX_DEPTH };
+use crate::object::Object;
+use crate::polygon::{ Vertex, Triangle };
+use super::ImportError;
+
+// Parsed triangles and their BVHs are stored next to big meshes as <mesh file>.bvhcache, loading them skips both
+// parsing and building. The cache is keyed by a hash of every file the objects were read from, the import options
+// and the BVH builder settings, so editing the mesh, its material libraries or the builder makes it stale.
+// DefaultHasher may change between Rust versions, that only costs one rebuild.
+//
+// Layout, little endian: magic, version u32, file paths, key u64, checksum u64 of the rest, objects, warnings.
+// Strings and arrays are prefixed with their u32 length
+
+const MAGIC: &[u8; 8] = b"RPTBVH\0\0";
+const VERSION: u32 = 1;
+// Smaller meshes build faster than the cache is read and would only litter the mesh folders
+const MIN_TRIANGLES: usize = 65536;
+
+// Objects as they were imported, without materials. files are the same for every object
+pub(super) struct Cached {
+    pub objects: Vec<Object>,
+    pub warnings: Vec<ImportError>,
+}
+
+fn cache_path(file_path: &str) -> String {
+    format!("{}.bvhcache", file_path)
+}
+
+// None when the files can't be read anymore
+fn key(files: &[String], options: &str) -> Option<u64> {
+    let mut hasher = DefaultHasher::new();
+    hasher.write_u32(VERSION);
+    hasher.write(options.as_bytes());
+    hasher.write(BVH::settings().as_bytes());
+    for file in files {
+        let bytes = std::fs::read(file).ok()?;
+        hasher.write_usize(bytes.len());
+        hasher.write(&bytes);
+    }
+    Some(hasher.finish())
+}
+
+// None if there is no cache, it is stale or it can't be read
+pub(super) fn read(file_path: &str, options: &str) -> Option<Cached> {
+    let bytes = std::fs::read(cache_path(file_path)).ok()?;
+    let mut reader = Reader { bytes: &bytes, position: 0 };
+    if reader.take(MAGIC.len())? != MAGIC || reader.u32()? != VERSION {
+        return None;
+    }
+    let files = reader.array(|reader| reader.string())?;
+    if files.first() != Some(&file_path.to_string()) || reader.u64()? != key(&files, options)? {
+        return None;
+    }
+    // Damaged triangles would still load, just wrong
+    if reader.u64()? != checksum(&bytes[reader.position..]) {
+        return None;
+    }
+
+    let objects = reader.array(|reader| {
+        let name = reader.string()?;
+        let triangles = reader.array(|reader| {
+            let vertices = [reader.vertex()?, reader.vertex()?, reader.vertex()?];
+            Some(Arc::new(RwLock::new(Triangle { vertices, material: reader.u32()? as usize })))
+        })?;
+        let nodes = reader.array(|reader| Some(BVHNode { aabb: AABB { min: reader.vec3()?, max: reader.vec3()? }, first: reader.u32()?, count: reader.u32()? }))?;
+        let indices = reader.array(|reader| reader.u32())?;
+        let bvh = BVH { nodes, indices };
+        if !is_valid(&bvh, triangles.len()) {
+            return None;
+        }
+        let mut object = Object::with_bvh(triangles, bvh);
+        object.name = name;
+        object.files = files.clone();
+        Some(object)
+    })?;
+    let warnings = reader.array(|reader| {
+        let line = reader.u32()?;
+        Some(ImportError::new(file_path, Some(line as usize).filter(|line| *line > 0), reader.string()?))
+    })?;
+    for object in &objects {
+        println!("BVH: {}, read from cache", object.bvh.stats());
+    }
+    Some(Cached { objects, warnings })
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+// Only big meshes are cached, failing to write the cache isn't an error
+pub(super) fn write(file_path: &str, options: &str, objects: &[Object], warnings: &[ImportError]) {
+    if objects.iter().map(|object| object.triangles.len()).sum::<usize>() < MIN_TRIANGLES {
+        return;
+    }
+    let Some(files) = objects.first().map(|object| &object.files) else { return };
+    let Some(key) = key(files, options) else { return };
+
+    let path = cache_path(file_path);
+    match std::fs::write(&path, encode(files, key, objects, warnings)) {
+        Ok(()) => println!("Wrote BVH cache {}", path),
+        Err(e) => eprintln!("Cannot write BVH cache {}: {}", path, e),
+    }
+}
+
+fn encode(files: &[String], key: u64, objects: &[Object], warnings: &[ImportError]) -> Vec<u8> {
+    let mut writer = Writer { bytes: Vec::new() };
+    writer.bytes.extend_from_slice(MAGIC);
+    writer.u32(VERSION);
+    writer.u32(files.len() as u32);
+    files.iter().for_each(|file| writer.string(file));
+    writer.bytes.extend_from_slice(&key.to_le_bytes());
+    let header = writer.bytes.len();
+
+    writer.u32(objects.len() as u32);
+    for object in objects {
+        writer.string(&object.name);
+        writer.u32(object.triangles.len() as u32);
+        for triangle in &object.triangles {
+            let triangle = triangle.read().unwrap();
+            triangle.vertices.iter().for_each(|vertex| writer.vertex(vertex));
+            writer.u32(triangle.material as u32);
+        }
+        writer.u32(object.bvh.nodes.len() as u32);
+        for node in &object.bvh.nodes {
+            writer.vec3(node.aabb.min);
+            writer.vec3(node.aabb.max);
+            writer.u32(node.first);
+            writer.u32(node.count);
+        }
+        writer.u32(object.bvh.indices.len() as u32);
+        object.bvh.indices.iter().for_each(|index| writer.u32(*index));
+    }
+    writer.u32(warnings.len() as u32);
+    for warning in warnings {
+        writer.u32(warning.line.unwrap_or(0) as u32);
+        writer.string(&warning.reason);
+    }
+
+    let checksum = checksum(&writer.bytes[header..]);
+    writer.bytes.splice(header..header, checksum.to_le_bytes());
+    writer.bytes
+}
+
+// Traversal trusts the tree, so a damaged cache must not reach it
+fn is_valid(bvh: &BVH, triangle_count: usize) -> bool {
+    let nodes_valid = bvh.nodes.iter().enumerate().all(|(index, node)| if node.is_leaf() {
+        node.first as usize + node.count as usize <= bvh.indices.len()
+    } else {
+        // Children come after their parent, refit depends on it
+        node.first as usize > index && (node.first as usize + 1) < bvh.nodes.len()
+    });
+    if !nodes_valid || bvh.indices.iter().any(|index| (*index as usize) >= triangle_count) {
+        return false;
+    }
+
+    // Traversal has a stack for MAX_DEPTH levels. Parents come first, so one pass sees every parent before its children
+    let mut depths = vec![1; bvh.nodes.len()];
+    for (index, node) in bvh.nodes.iter().enumerate().filter(|(_, node)| !node.is_leaf()) {
+        for child in [node.first as usize, node.first as usize + 1] {
+            depths[child] = depths[child].max(depths[index] + 1);
+        }
+    }
+    depths.iter().all(|depth| *depth <= MAX_DEPTH)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.position..self.position.checked_add(count)?)?;
+        self.position += count;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        self.u32().map(f32::from_bits)
+    }
+
+    fn vec3(&mut self) -> Option<Vec3> {
+        Some(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let length = self.u32()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).ok()
+    }
+
+    // Missing normals are stored as NaN
+    fn vertex(&mut self) -> Option<Vertex> {
+        let mut vertex = Vertex::new(self.vec3()?, Vec2::new(self.f32()?, self.f32()?));
+        vertex.normal = Some(self.vec3()?).filter(|normal| !normal.is_nan());
+        vertex.color = self.vec3()?;
+        Some(vertex)
+    }
+
+    // Length is checked against the remaining bytes first, so a damaged length can't allocate gigabytes
+    fn array<T>(&mut self, mut element: impl FnMut(&mut Self) -> Option<T>) -> Option<Vec<T>> {
+        let length = self.u32()? as usize;
+        if length > self.bytes.len() - self.position {
+            return None;
+        }
+        (0..length).map(|_| element(self)).collect()
+    }
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.u32(value.to_bits());
+    }
+
+    fn vec3(&mut self, value: Vec3) {
+        value.to_array().into_iter().for_each(|value| self.f32(value));
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn vertex(&mut self, vertex: &Vertex) {
+        self.vec3(vertex.pos);
+        self.f32(vertex.uv.x);
+        self.f32(vertex.uv.y);
+        self.vec3(vertex.normal.unwrap_or(Vec3::NAN));
+        self.vec3(vertex.color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPTIONS: &str = "test";
+
+    // A source file and its cache in the temp folder, both removed when the test ends
+    struct Fixture {
+        source: String,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let source = std::env::temp_dir().join(format!("rustpt_cache_{}_{}.obj", name, std::process::id()));
+            std::fs::write(&source, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+            Self { source: source.to_string_lossy().into_owned() }
+        }
+
+        // Written directly, write skips meshes this small
+        fn write(&self, objects: &[Object], warnings: &[ImportError]) -> Vec<u8> {
+            let files = vec![self.source.clone()];
+            let bytes = encode(&files, key(&files, OPTIONS).unwrap(), objects, warnings);
+            std::fs::write(cache_path(&self.source), &bytes).unwrap();
+            bytes
+        }
+
+        fn read(&self) -> Option<Cached> {
+            read(&self.source, OPTIONS)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.source);
+            let _ = std::fs::remove_file(cache_path(&self.source));
+        }
+    }
+
+    fn object(fixture: &Fixture) -> Object {
+        let triangles = (0..20).map(|i| {
+            let offset = Vec3::new(i as f32, (i % 3) as f32, 0.);
+            let mut vertices = [Vec3::ZERO, Vec3::X, Vec3::Y].map(|corner| Vertex::new(corner + offset, Vec2::new(0.5, i as f32)));
+            vertices[0].normal = Some(Vec3::Z);
+            vertices[1].color = Vec3::new(0.25, 0.5, 0.75);
+            Triangle { vertices, material: i % 2 }
+        }).collect();
+        let mut object = Object::new(triangles);
+        object.name = "mesh".to_string();
+        object.files = vec![fixture.source.clone()];
+        object
+    }
+
+    // Each inner node has a leaf and the next inner node as children. depth counts the root and the last leaf
+    fn chain(depth: usize) -> BVH {
+        let leaf = BVHNode { aabb: AABB::empty(), first: 0, count: 1 };
+        let mut nodes = Vec::new();
+        for _ in 0..depth - 1 {
+            nodes.push(BVHNode { aabb: AABB::empty(), first: nodes.len() as u32 + 1, count: 0 });
+            nodes.push(leaf.clone());
+        }
+        nodes.push(leaf);
+        BVH { nodes, indices: vec![0] }
+    }
+
+    #[test]
+    fn round_trip() {
+        let fixture = Fixture::new("round_trip");
+        let object = object(&fixture);
+        fixture.write(std::slice::from_ref(&object), &[ImportError::new(&fixture.source, Some(3), "warning")]);
+
+        let cached = fixture.read().unwrap();
+        assert_eq!(cached.objects.len(), 1);
+        let loaded = &cached.objects[0];
+        assert_eq!(loaded.name, object.name);
+        assert_eq!(loaded.files, object.files);
+        assert_eq!(loaded.aabb, object.aabb);
+        for (a, b) in loaded.triangles.iter().zip(&object.triangles) {
+            let (a, b) = (a.read().unwrap(), b.read().unwrap());
+            assert_eq!(a.material, b.material);
+            for (a, b) in a.vertices.iter().zip(&b.vertices) {
+                assert_eq!((a.pos, a.uv, a.normal, a.color), (b.pos, b.uv, b.normal, b.color));
+            }
+        }
+        assert_eq!(loaded.bvh.indices, object.bvh.indices);
+        assert!(loaded.bvh.nodes.iter().zip(&object.bvh.nodes).all(|(a, b)| a.aabb == b.aabb && a.first == b.first && a.count == b.count));
+        assert_eq!(cached.warnings.len(), 1);
+        assert_eq!((cached.warnings[0].line, cached.warnings[0].reason.as_str()), (Some(3), "warning"));
+    }
+
+    #[test]
+    fn truncated_cache_is_ignored() {
+        let fixture = Fixture::new("truncated");
+        let bytes = fixture.write(&[object(&fixture)], &[]);
+        for length in [0, 10, bytes.len() / 2, bytes.len() - 1] {
+            std::fs::write(cache_path(&fixture.source), &bytes[..length]).unwrap();
+            assert!(fixture.read().is_none(), "read {} of {} bytes", length, bytes.len());
+        }
+    }
+
+    #[test]
+    fn damaged_cache_is_ignored() {
+        let fixture = Fixture::new("damaged");
+        let mut bytes = fixture.write(&[object(&fixture)], &[]);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        std::fs::write(cache_path(&fixture.source), &bytes).unwrap();
+        assert!(fixture.read().is_none());
+    }
+
+    #[test]
+    fn changed_source_makes_cache_stale() {
+        let fixture = Fixture::new("changed_source");
+        fixture.write(&[object(&fixture)], &[]);
+        assert!(fixture.read().is_some());
+        let mut source = std::fs::read(&fixture.source).unwrap();
+        source[2] = b'1';
+        std::fs::write(&fixture.source, source).unwrap();
+        assert!(fixture.read().is_none());
+    }
+
+    #[test]
+    fn backwards_node_is_rejected() {
+        let fixture = Fixture::new("backwards");
+        let mut object = object(&fixture);
+        let inner = object.bvh.nodes.iter().position(|node| !node.is_leaf()).unwrap();
+        object.bvh.nodes[inner].first = inner as u32;
+        assert!(!is_valid(&object.bvh, object.triangles.len()));
+        fixture.write(&[object], &[]);
+        assert!(fixture.read().is_none());
+    }
+
+    #[test]
+    fn too_deep_tree_is_rejected() {
+        assert!(is_valid(&chain(MAX_DEPTH), 1));
+        assert!(!is_valid(&chain(MAX_DEPTH + 1), 1));
+
+        let fixture = Fixture::new("too_deep");
+        let mut object = object(&fixture);
+        object.bvh = chain(MAX_DEPTH + 1);
+        fixture.write(&[object], &[]);
+        assert!(fixture.read().is_none());
+    }
+}
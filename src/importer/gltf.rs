@@ -0,0 +1,221 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use glam::{ EulerRot, Mat3, Mat4, Quat, Vec2, Vec3 };
+use ::gltf::camera::Projection;
+use ::gltf::image::Format;
+use ::gltf::mesh::Mode;
+
+use crate::object::Object;
+use crate::camera::Camera;
+use crate::material::{ Material, Bsdf };
+use crate::polygon::{ Vertex, Triangle };
+use crate::texture::Texture;
+use super::{ Importer, ImportError };
+
+// Everything a glTF scene describes that we can render
+pub struct GltfScene {
+    pub objects: Vec<Object>, // One per node with a mesh
+    pub camera: Option<Camera>, // First camera found in the node hierarchy
+    pub warnings: Vec<ImportError>, // Only lenient imports produce warnings
+}
+
+impl Importer {
+    // Reads .gltf with embedded or external buffers and .glb files. Lenient imports drop triangles that reference
+    // missing vertices and warn about them, otherwise they fail the import
+    pub fn gltf(file_path: &str, lenient: bool) -> Result<GltfScene, ImportError> {
+        let (document, buffers, images) = ::gltf::import(file_path).map_err(|e| ImportError::new(file_path, None, e.to_string()))?;
+
+        let textures: Vec<Option<Arc<Texture>>> = images.iter().map(|image| image_to_texture(image).map(Arc::new)).collect();
+        // Primitives without a material use the last one
+        let mut materials: Vec<Material> = document.materials().map(|material| convert_material(&material, &textures)).collect();
+        materials.push(Material::default());
+
+        let Some(scene) = document.default_scene().or_else(|| document.scenes().next()) else {
+            return Err(ImportError::new(file_path, None, "File has no scenes"));
+        };
+        let mut gltf_scene = GltfScene { objects: Vec::new(), camera: None, warnings: Vec::new() };
+        let context = NodeContext { file_path, lenient, buffers: &buffers, materials: &materials };
+        for node in scene.nodes() {
+            visit_node(&node, Mat4::IDENTITY, &context, &mut gltf_scene)?;
+        }
+        let files = external_files(file_path, &document);
+        for object in &mut gltf_scene.objects {
+            object.files = files.clone();
+        }
+
+        if gltf_scene.objects.is_empty() {
+            return Err(ImportError::new(file_path, None, "File has no meshes"));
+        }
+        Ok(gltf_scene)
+    }
+}
+
+// What every node of a file is imported with
+struct NodeContext<'a> {
+    file_path: &'a str,
+    lenient: bool,
+    buffers: &'a [::gltf::buffer::Data],
+    materials: &'a [Material],
+}
+
+fn visit_node(node: &::gltf::Node, parent: Mat4, context: &NodeContext, gltf_scene: &mut GltfScene) -> Result<(), ImportError> {
+    let materials = context.materials;
+    let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+    let (_, rotation, translation) = transform.to_scale_rotation_translation();
+
+    if let Some(mesh) = node.mesh() {
+        // Rotation and scale are baked into the vertices, translation becomes the origin so the object can still be moved
+        let linear = Mat3::from_mat4(transform);
+        let normal_matrix = linear.inverse().transpose();
+        let mut triangles = Vec::new();
+        for primitive in mesh.primitives() {
+            let material = primitive.material().index().unwrap_or(materials.len() - 1);
+            let reader = primitive.reader(|buffer| Some(&context.buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else { continue };
+            let vertices: Vec<Vertex> = {
+                let mut normals = reader.read_normals();
+                let mut uvs = reader.read_tex_coords(0).map(|uvs| uvs.into_f32());
+                let mut colors = reader.read_colors(0).map(|colors| colors.into_rgb_f32());
+                positions.map(|position| {
+                    // glTF V points down the image, ours points up
+                    let uv = uvs.as_mut().and_then(Iterator::next).map_or(Vec2::ZERO, |[u, v]| Vec2::new(u, 1. - v));
+                    let mut vertex = Vertex::new(linear * Vec3::from(position), uv);
+                    vertex.normal = normals.as_mut().and_then(Iterator::next).map(|normal| (normal_matrix * Vec3::from(normal)).normalize_or_zero());
+                    // Already linear, unlike OBJ vertex colors
+                    vertex.color = colors.as_mut().and_then(Iterator::next).map_or(Vec3::ONE, Vec3::from);
+                    vertex
+                }).collect()
+            };
+            let indices: Vec<usize> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().map(|index| index as usize).collect(),
+                None => (0..vertices.len()).collect(),
+            };
+            let corners: Vec<[usize; 3]> = match primitive.mode() {
+                Mode::Triangles => indices.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect(),
+                // Every other strip triangle is flipped to keep the winding consistent
+                Mode::TriangleStrip => indices.windows(3).enumerate().map(|(i, w)| if i % 2 == 0 { [w[0], w[1], w[2]] } else { [w[1], w[0], w[2]] }).collect(),
+                Mode::TriangleFan => indices.windows(2).skip(1).map(|w| [indices[0], w[0], w[1]]).collect(),
+                // Points and lines have no surface to hit
+                _ => Vec::new(),
+            };
+            // Whole triangles are dropped, skipping single indices would shift the corners of every later one
+            let valid = |corners: &[usize; 3]| corners.iter().all(|&corner| corner < vertices.len());
+            if let Some(index) = corners.iter().flatten().find(|&&corner| corner >= vertices.len()) {
+                let dropped = corners.iter().filter(|corners| !valid(corners)).count();
+                let reason = format!("Mesh {} primitive {}: {} triangles reference missing vertices, e.g. vertex {} of {}",
+                    mesh.index(), primitive.index(), dropped, index, vertices.len());
+                if !context.lenient {
+                    return Err(ImportError::new(context.file_path, None, reason));
+                }
+                gltf_scene.warnings.push(ImportError::new(context.file_path, None, reason));
+            }
+            triangles.extend(corners.into_iter().filter(valid).map(|[a, b, c]| Triangle {
+                vertices: [vertices[a].clone(), vertices[b].clone(), vertices[c].clone()],
+                material,
+            }));
+        }
+
+        if !triangles.is_empty() {
+            let mut object = Object::new(triangles);
+            object.name = node.name().or(mesh.name()).map_or_else(|| format!("mesh {}", mesh.index()), str::to_string);
+            object.origin = translation;
+            object.materials = materials.to_vec(); // Textures are shared between the copies
+            gltf_scene.objects.push(object);
+        }
+    }
+
+    if let Some(camera) = node.camera().filter(|_| gltf_scene.camera.is_none()) {
+        gltf_scene.camera = Some(convert_camera(&camera, translation, rotation));
+    }
+
+    for child in node.children() {
+        visit_node(&child, transform, context, gltf_scene)?;
+    }
+    Ok(())
+}
+
+// The file itself and the .bin buffers and images it references, embedded data has no file to watch
+fn external_files(file_path: &str, document: &::gltf::Document) -> Vec<String> {
+    let directory = Path::new(file_path).parent().unwrap_or(Path::new(""));
+    let buffers = document.buffers().filter_map(|buffer| match buffer.source() {
+        ::gltf::buffer::Source::Uri(uri) => Some(uri),
+        ::gltf::buffer::Source::Bin => None,
+    });
+    let images = document.images().filter_map(|image| match image.source() {
+        ::gltf::image::Source::Uri { uri, .. } => Some(uri),
+        ::gltf::image::Source::View { .. } => None,
+    });
+    let uris = buffers.chain(images).filter(|uri| !uri.starts_with("data:"));
+    std::iter::once(file_path.to_string()).chain(uris.map(|uri| directory.join(uri).to_string_lossy().into_owned())).collect()
+}
+
+// Both look down -Z with Y up, so only the angles need converting
+fn convert_camera(camera: &::gltf::Camera, translation: Vec3, rotation: Quat) -> Camera {
+    let (yaw, pitch, roll) = rotation.to_euler(EulerRot::YXZ);
+    let mut result = Camera::new(translation, Vec3::new(yaw.to_degrees(), pitch.to_degrees(), roll.to_degrees()));
+    match camera.projection() {
+        Projection::Perspective(perspective) => result.fov = (perspective.yfov() as f64).to_degrees(),
+        Projection::Orthographic(orthographic) => {
+            result.orthographic = true;
+            result.ortho_size = orthographic.ymag() * 2.;
+        },
+    }
+    result
+}
+
+// Metallic-roughness is approximated with our bsdfs. Metallic-roughness, normal and emissive textures are ignored
+fn convert_material(material: &::gltf::Material, textures: &[Option<Arc<Texture>>]) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, alpha] = pbr.base_color_factor();
+    let base_color = Vec3::new(r, g, b);
+    let transmission = material.transmission().map_or(0., |transmission| transmission.transmission_factor());
+    let blended = material.alpha_mode() == ::gltf::material::AlphaMode::Blend && alpha < 1.;
+
+    let mut result = if transmission > 0.5 || blended {
+        let mut glass = Material::dielectric(material.ior().unwrap_or(1.5));
+        glass.albedo = base_color;
+        glass
+    } else if pbr.metallic_factor() >= 0.5 {
+        Material::metal(base_color, pbr.roughness_factor())
+    } else {
+        Material::lambertian(base_color)
+    };
+
+    // Only the first uv set is imported
+    if let Some(info) = pbr.base_color_texture().filter(|info| info.tex_coord() == 0) {
+        result.albedo_texture = textures.get(info.texture().source().index()).cloned().flatten();
+    }
+    result.emission = Vec3::from(material.emissive_factor()) * material.emissive_strength().unwrap_or(1.);
+    if result.emission.max_element() > 0. && base_color.max_element() <= 0. {
+        result.bsdf = Bsdf::Emissive;
+    }
+    result
+}
+
+// Alpha and channels past 8 bits are dropped. Float images aren't supported
+fn image_to_texture(image: &::gltf::image::Data) -> Option<Texture> {
+    let (width, height) = (image.width as usize, image.height as usize);
+    let (channels, bytes_per_channel) = match image.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        _ => return None,
+    };
+    let rgb: Vec<u8> = image.pixels.chunks_exact(channels * bytes_per_channel).flat_map(|pixel| {
+        let channel = |i: usize| match bytes_per_channel {
+            2 => (u16::from_ne_bytes([pixel[i * 2], pixel[i * 2 + 1]]) >> 8) as u8,
+            _ => pixel[i],
+        };
+        match channels {
+            1 | 2 => [channel(0); 3], // Grey, with alpha for two channels
+            _ => [channel(0), channel(1), channel(2)],
+        }
+    }).collect();
+    Some(Texture::new_from_rgb(width, height, &rgb))
+}
@@ -0,0 +1,200 @@
+use std::io::{BufRead, BufReader};
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use glam::Vec3;
+
+use crate::material::{ Material, Bsdf };
+use crate::texture::Texture;
+use super::{ Importer, ImportError, parse_number };
+
+// Everything read from one newmtl block, converted to a Material once the block ends
+struct MtlEntry {
+    name: String,
+    line: usize,
+    diffuse: Vec3,      // Kd
+    specular: Vec3,     // Ks
+    emission: Vec3,     // Ke
+    shininess: f32,     // Ns
+    ior: f32,           // Ni
+    opacity: f32,       // d, or 1 - Tr
+    transmission: Vec3, // Tf
+    diffuse_map: Option<String>,
+    bump_map: Option<(String, f32)>,
+}
+
+impl MtlEntry {
+    fn new(name: &str, line: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            line,
+            diffuse: Vec3::splat(0.8),
+            specular: Vec3::ZERO,
+            emission: Vec3::ZERO,
+            shininess: 0.,
+            ior: 1.5,
+            opacity: 1.,
+            transmission: Vec3::ONE,
+            diffuse_map: None,
+            bump_map: None,
+        }
+    }
+
+    // Picks the closest bsdf, MTL describes Phong style materials so this is an approximation
+    fn to_material(&self) -> Material {
+        let mut material = if self.opacity < 1. {
+            let mut glass = Material::dielectric(self.ior.max(1.));
+            glass.albedo = self.transmission;
+            glass
+        } else if self.specular.max_element() > self.diffuse.max_element() {
+            // Common approximation of roughness from a Phong exponent
+            Material::metal(self.specular, (2. / (self.shininess + 2.)).sqrt())
+        } else {
+            Material::lambertian(self.diffuse)
+        };
+        material.emission = self.emission;
+        if self.emission.max_element() > 0. && self.diffuse.max_element() <= 0. {
+            material.bsdf = Bsdf::Emissive;
+        }
+        material
+    }
+}
+
+// Materials of an MTL library in file order, paired with their names
+pub type MaterialLibrary = Vec<(String, Material)>;
+
+impl Importer {
+    // Only used through mtllib, so warnings are handed back to the OBJ importer
+    pub(super) fn mtl_with_warnings(file_path: &str, lenient: bool) -> Result<(MaterialLibrary, Vec<ImportError>), ImportError> {
+        let file = File::open(file_path).map_err(|e| ImportError::new(file_path, None, format!("Cannot open file: {}", e)))?;
+        let directory = Path::new(file_path).parent().unwrap_or(Path::new(""));
+
+        let mut entries: Vec<MtlEntry> = Vec::new();
+        let mut warnings = Vec::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.map_err(|e| ImportError::new(file_path, Some(line_number), format!("Cannot read line: {}", e)))?;
+            match parse_line(&line, line_number, &mut entries) {
+                Ok(None) => {},
+                Ok(Some(warning)) => warnings.push(ImportError::new(file_path, Some(line_number), warning)),
+                Err(reason) if lenient => warnings.push(ImportError::new(file_path, Some(line_number), reason)),
+                Err(reason) => return Err(ImportError::new(file_path, Some(line_number), reason)),
+            }
+        }
+
+        let mut materials = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let mut material = entry.to_material();
+            let load = |name: &str| {
+                let texture_path = directory.join(name);
+                Texture::new_from_file(&texture_path.to_string_lossy()).map(Arc::new)
+            };
+            // In lenient mode textures that fail to load leave the flat color in place
+            if let Some(name) = &entry.diffuse_map {
+                match load(name) {
+                    Ok(texture) => material.albedo_texture = Some(texture),
+                    Err(e) if lenient => warnings.push(ImportError::new(file_path, Some(entry.line), e.to_string())),
+                    Err(e) => return Err(ImportError::new(file_path, Some(entry.line), e.to_string())),
+                }
+            }
+            if let Some((name, scale)) = &entry.bump_map {
+                match load(name) {
+                    Ok(texture) => {
+                        material.bump_texture = Some(texture);
+                        material.bump_scale = *scale;
+                    },
+                    Err(e) if lenient => warnings.push(ImportError::new(file_path, Some(entry.line), e.to_string())),
+                    Err(e) => return Err(ImportError::new(file_path, Some(entry.line), e.to_string())),
+                }
+            }
+            materials.push((entry.name, material));
+        }
+        Ok((materials, warnings))
+    }
+}
+
+// Err for malformed lines, Ok(Some) for lines that are valid but were ignored
+fn parse_line(line: &str, line_number: usize, entries: &mut Vec<MtlEntry>) -> Result<Option<String>, String> {
+    let mut splitted = line.split_whitespace();
+    let operation = splitted.next().unwrap_or("#"); // Empty lines are considered comments
+    let words: Vec<&str> = splitted.collect();
+    if operation.starts_with('#') {
+        return Ok(None);
+    }
+    if operation == "newmtl" {
+        let name = words.join(" ");
+        if name.is_empty() {
+            return Err("Material without a name".to_string());
+        }
+        entries.push(MtlEntry::new(&name, line_number));
+        return Ok(None);
+    }
+
+    let Some(entry) = entries.last_mut() else {
+        return Err(format!("'{}' before any newmtl", operation));
+    };
+    match operation {
+        "Kd" => entry.diffuse = parse_color(&words)?,
+        "Ks" => entry.specular = parse_color(&words)?,
+        "Ke" => entry.emission = parse_color(&words)?,
+        "Tf" => entry.transmission = parse_color(&words)?,
+        "Ns" => entry.shininess = parse_float(&words)?,
+        "Ni" => entry.ior = parse_float(&words)?,
+        "d" => entry.opacity = parse_float(&words)?,
+        "Tr" => entry.opacity = 1. - parse_float(&words)?,
+        "map_Kd" => entry.diffuse_map = Some(parse_map(&words)?.0),
+        "map_Bump" | "map_bump" | "bump" => entry.bump_map = Some(parse_map(&words)?),
+        // Phong parameters without a counterpart in our bsdfs
+        "Ka" | "illum" | "map_Ka" | "map_Ks" | "map_Ns" | "map_d" => {},
+        _ => return Ok(Some(format!("Unknown operation '{}'", operation))),
+    }
+    Ok(None)
+}
+
+fn parse_float(words: &[&str]) -> Result<f32, String> {
+    let word = words.first().ok_or("Missing value")?;
+    parse_number(word)
+}
+
+// "r g b" or a single value for grey. Spectral and CIE XYZ colors are not supported
+fn parse_color(words: &[&str]) -> Result<Vec3, String> {
+    let values = words.iter().map(|word| word.parse::<f32>().map_err(|_| format!("Invalid color '{}'", words.join(" ")))).collect::<Result<Vec<f32>, String>>()?;
+    match values.len() {
+        1 => Ok(Vec3::splat(values[0])),
+        3 => Ok(Vec3::new(values[0], values[1], values[2])),
+        count => Err(format!("Color with {} components is unsupported", count)),
+    }
+}
+
+// Texture map statement: options followed by the file name. Returns the file and the -bm multiplier
+fn parse_map(words: &[&str]) -> Result<(String, f32), String> {
+    let mut scale = 1.;
+    let mut i = 0;
+    while i < words.len() && words[i].starts_with('-') {
+        let option = words[i];
+        i += 1;
+        match option {
+            "-blendu" | "-blendv" | "-cc" | "-clamp" | "-imfchan" | "-type" => i += 1, // Exactly one word
+            "-bm" | "-boost" | "-texres" | "-mm" | "-o" | "-s" | "-t" => {
+                // Up to this many numbers follow, e.g. "-s 2" and "-s 2 2 1" are both valid
+                let max_values = match option { "-mm" => 2, "-o" | "-s" | "-t" => 3, _ => 1 };
+                let values: Vec<f32> = words[i..].iter().take(max_values).map_while(|word| word.parse().ok()).collect();
+                if values.is_empty() {
+                    return Err(format!("Missing value for texture option '{}'", option));
+                }
+                if option == "-bm" {
+                    scale = values[0];
+                }
+                i += values.len();
+            },
+            _ => return Err(format!("Unknown texture option '{}'", option)),
+        }
+    }
+    // Whatever is left is the file name, which may contain spaces
+    let name = words.get(i..).unwrap_or_default().join(" ");
+    if name.is_empty() {
+        return Err("Texture map without a file name".to_string());
+    }
+    Ok((name, scale))
+}
@@ -0,0 +1,265 @@
+use std::io::{BufRead, BufReader};
+use std::fs::File;
+use std::collections::HashMap;
+use std::path::{ Path, PathBuf };
+
+use glam::{Vec2, Vec3};
+
+use crate::object::Object;
+use crate::material::Material;
+use crate::polygon::{ Vertex, Triangle, triangulate };
+use super::{ cache, Importer, ImportError, file_stem, parse_floats, srgb_to_linear };
+
+impl Importer {
+    // Fails on the first malformed line
+    pub fn obj(file_path: &str) -> Result<Object, ImportError> {
+        Self::obj_cached(file_path, false, false).map(|(mut objects, _)| objects.remove(0))
+    }
+
+    // One object per `o`/`g` group, named after it. Faces before the first group go to an object named after the file
+    pub fn obj_groups(file_path: &str) -> Result<Vec<Object>, ImportError> {
+        Self::obj_groups_with_warnings(file_path, false).map(|(objects, _)| objects)
+    }
+
+    // Skips malformed lines, they are returned as warnings together with everything that could be read
+    pub fn obj_groups_lenient(file_path: &str) -> Result<(Vec<Object>, Vec<ImportError>), ImportError> {
+        Self::obj_groups_with_warnings(file_path, true)
+    }
+
+    fn obj_groups_with_warnings(file_path: &str, lenient: bool) -> Result<(Vec<Object>, Vec<ImportError>), ImportError> {
+        Self::obj_cached(file_path, lenient, true)
+    }
+
+    // Big meshes are read from a BVH cache when it is still valid and cached after parsing otherwise
+    fn obj_cached(file_path: &str, lenient: bool, grouped: bool) -> Result<(Vec<Object>, Vec<ImportError>), ImportError> {
+        let options = format!("obj grouped {} lenient {}", grouped, lenient);
+        if let Some(cached) = cache::read(file_path, &options) {
+            if let Some(objects) = with_cached_materials(cached.objects, lenient) {
+                return Ok((objects, cached.warnings));
+            }
+        }
+
+        let parser = Self::parse_obj(file_path, lenient)?;
+        let objects: Vec<Object> = if grouped {
+            parser.groups.into_iter().filter(|group| !group.faces.is_empty()).map(|group| {
+                let mut object = Object::new(group.faces);
+                object.name = group.name;
+                object.materials = parser.materials.clone(); // Textures are shared, Triangle::material stays valid
+                object.files = parser.files.clone();
+                object
+            }).collect()
+        } else {
+            let faces = parser.groups.into_iter().flat_map(|group| group.faces).collect();
+            let mut object = Object::new(faces);
+            object.name = file_stem(file_path);
+            object.materials = parser.materials;
+            object.files = parser.files;
+            vec![object]
+        };
+        cache::write(file_path, &options, &objects, &parser.warnings);
+        Ok((objects, parser.warnings))
+    }
+
+    fn parse_obj(file_path: &str, lenient: bool) -> Result<ObjParser, ImportError> {
+        let file = File::open(file_path).map_err(|e| ImportError::new(file_path, None, format!("Cannot open file: {}", e)))?;
+
+        let mut parser = ObjParser::new(file_path, lenient);
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line_number = Some(index + 1);
+            let line = line.map_err(|e| ImportError::new(file_path, line_number, format!("Cannot read line: {}", e)))?;
+            match parser.parse_line(&line) {
+                Ok(None) => {},
+                Ok(Some(warning)) => parser.warnings.push(ImportError::new(file_path, line_number, warning)),
+                Err(reason) if lenient => parser.warnings.push(ImportError::new(file_path, line_number, reason)),
+                Err(reason) => return Err(ImportError::new(file_path, line_number, reason)),
+            }
+        }
+
+        if parser.groups.iter().all(|group| group.faces.is_empty()) {
+            return Err(ImportError::new(file_path, None, "File has no faces"));
+        }
+        Ok(parser)
+    }
+}
+
+// Materials aren't cached, they are read from the libraries again in the order the OBJ listed them.
+// None if that fails, the OBJ is parsed from scratch then
+fn with_cached_materials(mut objects: Vec<Object>, lenient: bool) -> Option<Vec<Object>> {
+    let mut materials = vec![Material::default()];
+    for library in objects.first()?.files.iter().skip(1) {
+        let (library, _) = Importer::mtl_with_warnings(library, lenient).ok()?;
+        materials.extend(library.into_iter().map(|(_, material)| material));
+    }
+    for object in &mut objects {
+        if object.triangles.iter().any(|triangle| triangle.read().unwrap().material >= materials.len()) {
+            return None;
+        }
+        object.materials = materials.clone();
+    }
+    Some(objects)
+}
+
+struct ObjGroup {
+    name: String,
+    faces: Vec<Triangle>,
+}
+
+struct ObjParser {
+    directory: PathBuf, // mtllib paths are relative to the OBJ file
+    lenient: bool,
+    positions: Vec<Vec3>,
+    colors: Vec<Vec3>,
+    uvs: Vec<Vec2>,
+    normals: Vec<Vec3>,
+    groups: Vec<ObjGroup>,
+    current_group: usize,
+    materials: Vec<Material>, // First one is the default for faces before any usemtl
+    material_names: HashMap<String, usize>,
+    current_material: usize,
+    warnings: Vec<ImportError>,
+    files: Vec<String>, // The OBJ and its material libraries
+}
+
+impl ObjParser {
+    fn new(file_path: &str, lenient: bool) -> Self {
+        Self {
+            directory: Path::new(file_path).parent().unwrap_or(Path::new("")).to_path_buf(),
+            lenient,
+            positions: Vec::new(),
+            colors: Vec::new(),
+            uvs: Vec::new(),
+            normals: Vec::new(),
+            groups: vec![ObjGroup { name: file_stem(file_path), faces: Vec::new() }],
+            current_group: 0,
+            materials: vec![Material::default()],
+            material_names: HashMap::new(),
+            current_material: 0,
+            warnings: Vec::new(),
+            files: vec![file_path.to_string()],
+        }
+    }
+
+    // Err for malformed lines, Ok(Some) for lines that are valid but were ignored
+    fn parse_line(&mut self, line: &str) -> Result<Option<String>, String> {
+        let mut splitted = line.split_whitespace();
+        let operation = splitted.next().unwrap_or("#"); // Empty lines are considered comments
+        let words: Vec<&str> = splitted.collect();
+        match operation {
+            _ if operation.starts_with('#') => {},
+            "v" => {
+                let values = parse_floats(&words)?;
+                // x y z [w] [r g b], w is ignored
+                let color = match values.len() {
+                    3 | 4 => Vec3::ONE,
+                    6 => Vec3::new(values[3], values[4], values[5]),
+                    7 => Vec3::new(values[4], values[5], values[6]),
+                    count => return Err(format!("Vertex with {} components is unsupported", count)),
+                };
+                self.positions.push(Vec3::new(values[0], values[1], values[2]));
+                self.colors.push(srgb_to_linear(color));
+            },
+            "vt" => {
+                let values = parse_floats(&words)?;
+                if values.is_empty() || values.len() > 3 {
+                    return Err(format!("Texture coordinate with {} components is unsupported", values.len()));
+                }
+                self.uvs.push(Vec2::new(values[0], values.get(1).copied().unwrap_or(0.)));
+            },
+            "vn" => {
+                let values = parse_floats(&words)?;
+                if values.len() != 3 {
+                    return Err(format!("Normal with {} components is unsupported", values.len()));
+                }
+                self.normals.push(Vec3::new(values[0], values[1], values[2]).normalize_or_zero());
+            },
+            "f" => {
+                if words.len() < 3 {
+                    return Err(format!("Face with {} vertices, at least 3 are needed", words.len()));
+                }
+                let polygon = words.iter().map(|word| self.parse_face_vertex(word)).collect::<Result<Vec<Vertex>, String>>()?;
+
+                let corners: Vec<Vec3> = polygon.iter().map(|vertex| vertex.pos).collect();
+                triangulate(&corners).into_iter().for_each(|[a, b, c]| {
+                    self.groups[self.current_group].faces.push(Triangle { vertices: [
+                        polygon[a].clone(),
+                        polygon[b].clone(),
+                        polygon[c].clone(),
+                    ], material: self.current_material });
+                });
+            },
+            "mtllib" => {
+                // Libraries may be listed together, names with spaces aren't supported
+                for name in &words {
+                    let library_path = self.directory.join(name).to_string_lossy().into_owned();
+                    self.files.push(library_path.clone()); // Watched even if missing, so creating it triggers a reload
+                    match Importer::mtl_with_warnings(&library_path, self.lenient) {
+                        Ok((materials, warnings)) => {
+                            self.warnings.extend(warnings);
+                            for (name, material) in materials {
+                                self.material_names.insert(name, self.materials.len());
+                                self.materials.push(material);
+                            }
+                        },
+                        Err(e) => return Err(e.to_string()),
+                    }
+                }
+            },
+            "usemtl" => {
+                let name = words.join(" ");
+                match self.material_names.get(&name) {
+                    Some(index) => self.current_material = *index,
+                    None => {
+                        self.current_material = 0;
+                        return Err(format!("Unknown material '{}'", name));
+                    },
+                }
+            },
+            "o" | "g" => {
+                // Faces of a group may be split across the file, they all end up in the same object
+                let name = if words.is_empty() { "default".to_string() } else { words.join(" ") };
+                self.current_group = match self.groups.iter().position(|group| group.name == name) {
+                    Some(index) => index,
+                    None => {
+                        self.groups.push(ObjGroup { name, faces: Vec::new() });
+                        self.groups.len() - 1
+                    },
+                };
+            },
+            // Smoothing groups don't change geometry
+            "s" => {},
+            _ => return Ok(Some(format!("Unknown operation '{}'", operation))),
+        }
+        Ok(None)
+    }
+
+    // One corner of a face: v, v/vt, v//vn or v/vt/vn
+    fn parse_face_vertex(&self, word: &str) -> Result<Vertex, String> {
+        let mut indices = word.split('/');
+        let position = resolve_index(indices.next(), self.positions.len())?.ok_or("Face vertex without position index")?;
+        let uv = resolve_index(indices.next(), self.uvs.len())?;
+        let normal = resolve_index(indices.next(), self.normals.len())?;
+
+        let mut vertex = Vertex::new(self.positions[position], uv.map_or(Vec2::ZERO, |i| self.uvs[i]));
+        vertex.normal = normal.map(|i| self.normals[i]);
+        vertex.color = self.colors[position];
+        Ok(vertex)
+    }
+}
+
+// OBJ indices start at 1, negative ones count back from the last element defined so far.
+// Returns None for an empty or missing index, e.g. the texture coordinate in "1//3"
+fn resolve_index(word: Option<&str>, count: usize) -> Result<Option<usize>, String> {
+    let Some(word) = word.filter(|word| !word.is_empty()) else {
+        return Ok(None);
+    };
+    let index = word.parse::<i64>().map_err(|_| format!("Invalid index '{}'", word))?;
+    let resolved = match index {
+        0 => return Err("Index 0 is invalid, OBJ indices start at 1".to_string()),
+        i if i > 0 => i - 1,
+        i => count as i64 + i,
+    };
+    if resolved < 0 || resolved as usize >= count {
+        return Err(format!("Index {} is out of range, only {} elements are defined", index, count));
+    }
+    Ok(Some(resolved as usize))
+}
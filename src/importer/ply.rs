@@ -0,0 +1,473 @@
+use glam::{Vec2, Vec3};
+
+use crate::object::Object;
+use crate::polygon::{ Vertex, Triangle, triangulate };
+use super::{ Importer, ImportError, file_stem, parse_number, srgb_to_linear };
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum Scalar {
+    I8, U8, I16, U16, I32, U32, F32, F64,
+}
+
+impl Scalar {
+    // Both the original names and the sized ones newer exporters write
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => return Err(format!("Unknown property type '{}'", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    // Integer colors are 0..255, floats are already 0..1
+    fn color_scale(self) -> f64 {
+        match self {
+            Self::F32 | Self::F64 => 1.,
+            _ => 255.,
+        }
+    }
+}
+
+enum PropertyKind {
+    Scalar(Scalar),
+    List { count: Scalar, item: Scalar },
+}
+
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn find(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|property| names.contains(&property.name.as_str()))
+    }
+
+    // Like find, but the property has to be a list
+    fn find_list(&self, names: &[&str]) -> Result<Option<usize>, String> {
+        match self.find(names) {
+            Some(index) if matches!(self.properties[index].kind, PropertyKind::Scalar(_)) => {
+                Err(format!("Property {} of element {} has to be a list", self.properties[index].name, self.name))
+            },
+            found => Ok(found),
+        }
+    }
+
+    // Fewest bytes a row can take, lists may be empty. ASCII values need at least a digit and a separator.
+    // Never 0, so an element can't claim more rows than the file has bytes
+    fn min_row_size(&self, format: Format) -> usize {
+        let size = self.properties.iter().map(|property| match (format, &property.kind) {
+            (Format::Ascii, _) => 2,
+            (_, PropertyKind::Scalar(kind)) => kind.size(),
+            (_, PropertyKind::List { count, .. }) => count.size(),
+        }).sum::<usize>();
+        size.max(1)
+    }
+
+    fn scalar_type(&self, index: usize) -> Scalar {
+        match self.properties[index].kind {
+            PropertyKind::Scalar(kind) => kind,
+            PropertyKind::List { item, .. } => item,
+        }
+    }
+}
+
+// One element row: every property value in order, lists are flattened and their items follow the count
+struct Row {
+    values: Vec<f64>,
+    offsets: Vec<usize>, // Where each property starts in values
+}
+
+impl Row {
+    fn scalar(&self, property: usize) -> f64 {
+        self.values[self.offsets[property]]
+    }
+
+    fn list(&self, property: usize) -> &[f64] {
+        let start = self.offsets[property];
+        &self.values[start + 1..start + 1 + self.values[start] as usize]
+    }
+}
+
+impl Importer {
+    // ASCII and binary PLY. Vertices may have normals, uvs and colors, faces may be any polygon
+    pub fn ply(file_path: &str) -> Result<Object, ImportError> {
+        let bytes = std::fs::read(file_path).map_err(|e| ImportError::new(file_path, None, format!("Cannot open file: {}", e)))?;
+        let header = parse_header(&bytes).map_err(|(line, reason)| ImportError::new(file_path, Some(line), reason))?;
+
+        let body = &bytes[header.body_start..];
+        let mut reader = match header.format {
+            Format::Ascii => Reader::Ascii { bytes: body, position: 0, line: header.lines },
+            format => Reader::Binary { bytes: body, position: 0, big_endian: format == Format::BinaryBigEndian },
+        };
+        let error = |reader: &Reader, reason: String| ImportError::new(file_path, reader.line(), reason);
+        // Counts come from the header, checking them keeps a broken one from allocating or looping forever.
+        // The last ASCII value may lack its separator, hence the extra byte
+        if let Some(element) = header.elements.iter().find(|element| element.count > (body.len() + 1) / element.min_row_size(header.format)) {
+            return Err(ImportError::new(file_path, None, format!("Element {} has {} rows, more than the file can hold", element.name, element.count)));
+        }
+
+        let mut vertices: Vec<Vertex> = Vec::new();
+        let mut triangles: Vec<Triangle> = Vec::new();
+        let mut row = Row { values: Vec::new(), offsets: Vec::new() };
+        for element in &header.elements {
+            match element.name.as_str() {
+                "vertex" => {
+                    let layout = VertexLayout::new(element).map_err(|reason| ImportError::new(file_path, None, reason))?;
+                    vertices.reserve(element.count);
+                    for _ in 0..element.count {
+                        reader.read_row(element, &mut row).map_err(|reason| error(&reader, reason))?;
+                        vertices.push(layout.vertex(&row));
+                    }
+                },
+                "face" => {
+                    let header_error = |reason: String| ImportError::new(file_path, None, reason);
+                    let Some(indices) = element.find_list(&["vertex_indices", "vertex_index"]).map_err(header_error)? else {
+                        return Err(ImportError::new(file_path, None, "Face element without vertex_indices"));
+                    };
+                    // Some scanners store uvs per face corner instead of per vertex
+                    let corner_uvs = element.find_list(&["texcoord"]).map_err(header_error)?;
+                    triangles.reserve(element.count);
+                    for _ in 0..element.count {
+                        reader.read_row(element, &mut row).map_err(|reason| error(&reader, reason))?;
+                        let mut polygon = row.list(indices).iter().map(|&index| {
+                            let index = to_count(index).map_err(|_| format!("Invalid vertex index {}", index))?;
+                            vertices.get(index).cloned().ok_or_else(|| format!("Vertex index {} is out of range, only {} vertices are defined", index, vertices.len()))
+                        }).collect::<Result<Vec<Vertex>, String>>().map_err(|reason| error(&reader, reason))?;
+                        if let Some(uvs) = corner_uvs.map(|property| row.list(property)).filter(|uvs| uvs.len() == polygon.len() * 2) {
+                            polygon.iter_mut().zip(uvs.chunks_exact(2)).for_each(|(vertex, uv)| vertex.uv = Vec2::new(uv[0] as f32, uv[1] as f32));
+                        }
+                        if polygon.len() < 3 {
+                            continue; // Points and edges have no surface
+                        }
+                        let corners: Vec<Vec3> = polygon.iter().map(|vertex| vertex.pos).collect();
+                        triangles.extend(triangulate(&corners).into_iter().map(|[a, b, c]| Triangle {
+                            vertices: [polygon[a].clone(), polygon[b].clone(), polygon[c].clone()],
+                            material: 0,
+                        }));
+                    }
+                },
+                // Edges, materials and anything else still have to be read to reach the next element
+                _ => for _ in 0..element.count {
+                    reader.read_row(element, &mut row).map_err(|reason| error(&reader, reason))?;
+                },
+            }
+        }
+
+        if triangles.is_empty() {
+            return Err(ImportError::new(file_path, None, "File has no faces"));
+        }
+        let mut object = Object::new(triangles);
+        object.name = file_stem(file_path);
+        object.files = vec![file_path.to_string()];
+        Ok(object)
+    }
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+    body_start: usize, // Byte offset right after end_header
+    lines: usize,
+}
+
+// Errors carry a 1 based line number
+fn parse_header(bytes: &[u8]) -> Result<Header, (usize, String)> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut position = 0;
+    let mut line_number = 0;
+    loop {
+        let Some(length) = bytes[position..].iter().position(|byte| *byte == b'\n') else {
+            return Err((line_number, "Header without end_header".to_string()));
+        };
+        let line = String::from_utf8_lossy(&bytes[position..position + length]);
+        position += length + 1;
+        line_number += 1;
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["ply"] if line_number == 1 => {},
+            _ if line_number == 1 => return Err((line_number, "Not a PLY file".to_string())),
+            ["format", name, _version] => format = Some(match *name {
+                "ascii" => Format::Ascii,
+                "binary_little_endian" => Format::BinaryLittleEndian,
+                "binary_big_endian" => Format::BinaryBigEndian,
+                _ => return Err((line_number, format!("Unknown format '{}'", name))),
+            }),
+            ["comment", ..] | ["obj_info", ..] | [] => {},
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| (line_number, format!("Invalid element count '{}'", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let kind = PropertyKind::List { count: Scalar::parse(count).map_err(|e| (line_number, e))?, item: Scalar::parse(item).map_err(|e| (line_number, e))? };
+                let element = elements.last_mut().ok_or((line_number, "Property before any element".to_string()))?;
+                element.properties.push(Property { name: name.to_string(), kind });
+            },
+            ["property", kind, name] => {
+                let kind = PropertyKind::Scalar(Scalar::parse(kind).map_err(|e| (line_number, e))?);
+                let element = elements.last_mut().ok_or((line_number, "Property before any element".to_string()))?;
+                element.properties.push(Property { name: name.to_string(), kind });
+            },
+            ["end_header"] => break,
+            _ => return Err((line_number, format!("Invalid header line '{}'", line.trim()))),
+        }
+    }
+    let format = format.ok_or((line_number, "Header without format".to_string()))?;
+    Ok(Header { format, elements, body_start: position, lines: line_number })
+}
+
+enum Reader<'a> {
+    Ascii { bytes: &'a [u8], position: usize, line: usize }, // Every row is on its own line
+    Binary { bytes: &'a [u8], position: usize, big_endian: bool },
+}
+
+impl Reader<'_> {
+    fn line(&self) -> Option<usize> {
+        match self {
+            Reader::Ascii { line, .. } => Some(*line),
+            Reader::Binary { .. } => None,
+        }
+    }
+
+    fn read_row(&mut self, element: &Element, row: &mut Row) -> Result<(), String> {
+        row.values.clear();
+        row.offsets.clear();
+        match self {
+            Reader::Ascii { bytes, position, line } => {
+                *line += 1;
+                if *position >= bytes.len() {
+                    return Err(format!("File ends before all {} {} rows", element.count, element.name));
+                }
+                let length = bytes[*position..].iter().position(|byte| *byte == b'\n').unwrap_or(bytes.len() - *position);
+                let text = &bytes[*position..*position + length];
+                *position += length + 1;
+                let mut words = std::str::from_utf8(text).map_err(|_| "Invalid text".to_string())?.split_ascii_whitespace();
+                let mut next = || {
+                    let word = words.next().ok_or_else(|| format!("Not enough values for element {}", element.name))?;
+                    parse_number::<f64>(word)
+                };
+                for property in &element.properties {
+                    row.offsets.push(row.values.len());
+                    match property.kind {
+                        PropertyKind::Scalar(_) => row.values.push(next()?),
+                        PropertyKind::List { .. } => {
+                            let count = next()?;
+                            row.values.push(count);
+                            for _ in 0..to_count(count)? {
+                                row.values.push(next()?);
+                            }
+                        },
+                    }
+                }
+            },
+            Reader::Binary { bytes, position, big_endian } => {
+                for property in &element.properties {
+                    row.offsets.push(row.values.len());
+                    match property.kind {
+                        PropertyKind::Scalar(kind) => row.values.push(read_binary(bytes, position, *big_endian, kind)?),
+                        PropertyKind::List { count, item } => {
+                            let count = read_binary(bytes, position, *big_endian, count)?;
+                            row.values.push(count);
+                            for _ in 0..to_count(count)? {
+                                row.values.push(read_binary(bytes, position, *big_endian, item)?);
+                            }
+                        },
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+// List lengths and vertex indices are read as numbers of any type, only whole non-negative ones make sense
+fn to_count(value: f64) -> Result<usize, String> {
+    if value < 0. || value.fract() != 0. || value > u32::MAX as f64 {
+        return Err(format!("Invalid list length {}", value));
+    }
+    Ok(value as usize)
+}
+
+fn read_binary(bytes: &[u8], position: &mut usize, big_endian: bool, kind: Scalar) -> Result<f64, String> {
+    let data = bytes.get(*position..*position + kind.size()).ok_or_else(|| format!("File ends at byte {} of the body", bytes.len()))?;
+    *position += kind.size();
+    let mut buffer = [0u8; 8];
+    buffer[..data.len()].copy_from_slice(data);
+    if big_endian {
+        buffer[..data.len()].reverse();
+    }
+    // Bytes are little endian now
+    Ok(match kind {
+        Scalar::I8 => buffer[0] as i8 as f64,
+        Scalar::U8 => buffer[0] as f64,
+        Scalar::I16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+        Scalar::U16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+        Scalar::I32 => i32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+        Scalar::U32 => u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+        Scalar::F32 => f32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+        Scalar::F64 => f64::from_le_bytes(buffer),
+    })
+}
+
+// Which vertex properties hold what, resolved once from the header
+struct VertexLayout {
+    position: [usize; 3],
+    normal: Option<[usize; 3]>,
+    uv: Option<[usize; 2]>,
+    color: Option<([usize; 3], f64)>,
+}
+
+impl VertexLayout {
+    fn new(element: &Element) -> Result<Self, String> {
+        let find_all = |names: [&[&str]; 3]| Some([element.find(names[0])?, element.find(names[1])?, element.find(names[2])?]);
+        let position = find_all([&["x"], &["y"], &["z"]]).ok_or("Vertex element without x, y and z")?;
+        let normal = find_all([&["nx"], &["ny"], &["nz"]]);
+        let uv = element.find(&["u", "s", "texture_u", "texture_s"]).zip(element.find(&["v", "t", "texture_v", "texture_t"])).map(|(u, v)| [u, v]);
+        let color = find_all([&["red", "diffuse_red", "r"], &["green", "diffuse_green", "g"], &["blue", "diffuse_blue", "b"]])
+            .map(|color| (color, element.scalar_type(color[0]).color_scale()));
+        Ok(Self { position, normal, uv, color })
+    }
+
+    fn vertex(&self, row: &Row) -> Vertex {
+        let vec3 = |[x, y, z]: [usize; 3]| Vec3::new(row.scalar(x) as f32, row.scalar(y) as f32, row.scalar(z) as f32);
+        let uv = self.uv.map_or(Vec2::ZERO, |[u, v]| Vec2::new(row.scalar(u) as f32, row.scalar(v) as f32));
+        let mut vertex = Vertex::new(vec3(self.position), uv);
+        vertex.normal = self.normal.and_then(|normal| vec3(normal).try_normalize());
+        if let Some((color, scale)) = self.color {
+            // Scanners store colors like images, in sRGB
+            vertex.color = srgb_to_linear(vec3(color) / scale as f32);
+        }
+        vertex
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Importer::ply reads from disk, every test gets its own file
+    fn import(name: &str, bytes: &[u8]) -> Result<Object, ImportError> {
+        let path = std::env::temp_dir().join(format!("rustpt_ply_{}_{}.ply", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let result = Importer::ply(&path.to_string_lossy());
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn positions(object: &Object) -> Vec<[Vec3; 3]> {
+        object.triangles.iter().map(|triangle| triangle.read().unwrap().vertices.clone().map(|vertex| vertex.pos)).collect()
+    }
+
+    const TRIANGLE: [Vec3; 3] = [Vec3::ZERO, Vec3::X, Vec3::Y];
+
+    // One triangle with float positions and a uchar count, int index list
+    fn binary(format: &str, big_endian: bool) -> Vec<u8> {
+        let mut bytes = format!("ply\nformat {} 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+            element face 1\nproperty list uchar int vertex_indices\nend_header\n", format).into_bytes();
+        let f32_bytes = |value: f32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        let i32_bytes = |value: i32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        TRIANGLE.iter().flat_map(|corner| corner.to_array()).for_each(|value| bytes.extend(f32_bytes(value)));
+        bytes.push(3);
+        [0, 1, 2].into_iter().for_each(|index| bytes.extend(i32_bytes(index)));
+        bytes
+    }
+
+    fn ascii(face_property: &str, faces: &str) -> Vec<u8> {
+        format!("ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+            element face 1\n{}\nend_header\n0 0 0\n1 0 0\n0 1 0\n{}\n", face_property, faces).into_bytes()
+    }
+
+    #[test]
+    fn ascii_quad_with_colors() {
+        let bytes = b"ply\nformat ascii 1.0\ncomment quad\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+            property uchar red\nproperty uchar green\nproperty uchar blue\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n\
+            0 0 0 255 255 255\n1 0 0 255 255 255\n1 1 0 255 255 255\n0 1 0 0 0 0\n4 0 1 2 3\n";
+        let object = import("ascii", bytes).unwrap();
+        assert_eq!(object.triangles.len(), 2);
+        let colors: Vec<Vec3> = object.triangles.iter().flat_map(|triangle| triangle.read().unwrap().vertices.clone().map(|vertex| vertex.color)).collect();
+        assert!(colors.contains(&Vec3::ONE) && colors.contains(&Vec3::ZERO));
+    }
+
+    #[test]
+    fn binary_little_endian() {
+        let object = import("little_endian", &binary("binary_little_endian", false)).unwrap();
+        assert_eq!(positions(&object), vec![TRIANGLE]);
+    }
+
+    #[test]
+    fn binary_big_endian() {
+        let object = import("big_endian", &binary("binary_big_endian", true)).unwrap();
+        assert_eq!(positions(&object), vec![TRIANGLE]);
+    }
+
+    #[test]
+    fn ascii_triangle() {
+        let object = import("ascii_triangle", &ascii("property list uchar int vertex_indices", "3 0 1 2")).unwrap();
+        assert_eq!(positions(&object), vec![TRIANGLE]);
+    }
+
+    #[test]
+    fn scalar_vertex_indices_are_rejected() {
+        let error = import("scalar_indices", &ascii("property int vertex_indices", "9")).err().unwrap();
+        assert!(error.reason.contains("has to be a list"), "{}", error);
+    }
+
+    #[test]
+    fn scalar_texcoord_is_rejected() {
+        let error = import("scalar_texcoord", &ascii("property list uchar int vertex_indices\nproperty float texcoord", "3 0 1 2 0.5")).err().unwrap();
+        assert!(error.reason.contains("has to be a list"), "{}", error);
+    }
+
+    #[test]
+    fn negative_and_fractional_indices_are_rejected() {
+        for (name, face) in [("negative_index", "3 -1 1 2"), ("fractional_index", "3 0.5 1 2")] {
+            let error = import(name, &ascii("property list uchar int vertex_indices", face)).err().unwrap();
+            assert!(error.reason.contains("Invalid vertex index"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn impossible_counts_are_rejected() {
+        let bytes = b"ply\nformat binary_little_endian 1.0\nelement vertex 4000000000000\nproperty float x\nproperty float y\nproperty float z\nend_header\n";
+        let error = import("huge_count", bytes).err().unwrap();
+        assert!(error.reason.contains("more than the file can hold"), "{}", error);
+    }
+
+    #[test]
+    fn truncated_binary_body_is_an_error() {
+        let mut bytes = binary("binary_little_endian", false);
+        bytes.truncate(bytes.len() - 2);
+        assert!(import("truncated", &bytes).is_err());
+    }
+}
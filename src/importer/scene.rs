@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use glam::{ EulerRot, Mat3, Quat, Vec2, Vec3 };
+
+use crate::object::{ Object, Reloader };
+use crate::camera::{ Camera, FovAxis };
+use crate::material::Material;
+use crate::polygon::{ Vertex, Triangle };
+use crate::scene::{ Scene, RenderSettings };
+use crate::texture::Texture;
+use crate::{ DEFAULT_WIDTH, DEFAULT_HEIGHT };
+use super::{ Importer, ImportError, parse_floats, parse_number };
+
+// Scene files are made of blocks: a keyword line followed by property lines, '#' starts a comment.
+//
+//   settings                    width, height, spp, max_depth, sky_zenith, sky_horizon
+//   camera                      position, rotation (yaw pitch roll), fov, fov_axis, orthographic <size>, aperture, focus
+//   material <name>             bsdf lambertian|metal|dielectric|emissive, albedo, roughness, ior, emission, texture, bump <file> [scale]
+//   object <name>               file, origin, rotation, scale, use_material <material name>, weld
+//   light <name>                position, rotation, size <width> <depth>, color, intensity
+//
+// Paths are relative to the scene file. Colors and scales take one value for all channels or three.
+
+#[derive(Default)]
+struct CameraEntry {
+    position: Vec3,
+    rotation: Vec3,
+    fov: Option<f64>,
+    fov_axis: Option<FovAxis>,
+    ortho_size: Option<f32>,
+    aperture: Option<f32>,
+    focus_distance: Option<f32>,
+}
+
+#[derive(Clone)]
+struct MaterialEntry {
+    bsdf: String,
+    albedo: Vec3,
+    roughness: f32,
+    ior: f32,
+    emission: Vec3,
+    texture: Option<String>,
+    bump: Option<(String, f32)>,
+}
+
+#[derive(Clone)]
+struct ObjectEntry {
+    name: String,
+    line: usize,
+    file: Option<String>,
+    origin: Vec3,
+    rotation: Vec3,
+    scale: Vec3,
+    material: Option<String>,
+    weld: bool,
+}
+
+// Rectangular area light, lights are emissive geometry like everything else
+struct LightEntry {
+    name: String,
+    position: Vec3,
+    rotation: Vec3,
+    size: Vec2,
+    color: Vec3,
+    intensity: f32,
+}
+
+enum Block {
+    None,
+    Settings,
+    Camera,
+    Material(String),
+    Object(usize),
+    Light(usize),
+}
+
+struct SceneParser {
+    block: Block,
+    settings: RenderSettings,
+    camera: Option<CameraEntry>,
+    materials: HashMap<String, (usize, MaterialEntry)>, // With the line they were defined on
+    objects: Vec<ObjectEntry>,
+    lights: Vec<LightEntry>,
+}
+
+impl Importer {
+    pub fn scene(file_path: &str) -> Result<Scene, ImportError> {
+        let file = File::open(file_path).map_err(|e| ImportError::new(file_path, None, format!("Cannot open file: {}", e)))?;
+        let directory = Path::new(file_path).parent().unwrap_or(Path::new("")).to_path_buf();
+
+        let mut parser = SceneParser {
+            block: Block::None,
+            settings: RenderSettings::new(DEFAULT_WIDTH, DEFAULT_HEIGHT),
+            camera: None,
+            materials: HashMap::new(),
+            objects: Vec::new(),
+            lights: Vec::new(),
+        };
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.map_err(|e| ImportError::new(file_path, Some(line_number), format!("Cannot read line: {}", e)))?;
+            parser.parse_line(&line, line_number).map_err(|reason| ImportError::new(file_path, Some(line_number), reason))?;
+        }
+
+        parser.build(file_path, &directory)
+    }
+}
+
+impl SceneParser {
+    fn parse_line(&mut self, line: &str, line_number: usize) -> Result<(), String> {
+        let line = line.split('#').next().unwrap_or_default();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&keyword, values)) = words.split_first() else {
+            return Ok(());
+        };
+        let name = values.join(" ");
+
+        match keyword {
+            "settings" => self.block = Block::Settings,
+            "camera" => {
+                self.camera = Some(CameraEntry::default());
+                self.block = Block::Camera;
+            },
+            "material" => {
+                if name.is_empty() {
+                    return Err("Material without a name".to_string());
+                }
+                if let Some((line, _)) = self.materials.get(&name) {
+                    return Err(format!("Material '{}' is already defined on line {}", name, line));
+                }
+                self.materials.insert(name.clone(), (line_number, MaterialEntry {
+                    bsdf: "lambertian".to_string(),
+                    albedo: Vec3::splat(0.8),
+                    roughness: 0.,
+                    ior: 1.5,
+                    emission: Vec3::ZERO,
+                    texture: None,
+                    bump: None,
+                }));
+                self.block = Block::Material(name);
+            },
+            "object" => {
+                self.objects.push(ObjectEntry { name, line: line_number, file: None, origin: Vec3::ZERO, rotation: Vec3::ZERO, scale: Vec3::ONE, material: None, weld: false });
+                self.block = Block::Object(self.objects.len() - 1);
+            },
+            "light" => {
+                self.lights.push(LightEntry { name, position: Vec3::ZERO, rotation: Vec3::ZERO, size: Vec2::ONE, color: Vec3::ONE, intensity: 1. });
+                self.block = Block::Light(self.lights.len() - 1);
+            },
+            _ => return self.parse_property(keyword, values),
+        }
+        Ok(())
+    }
+
+    fn parse_property(&mut self, property: &str, values: &[&str]) -> Result<(), String> {
+        let unknown = || Err(format!("Unknown property '{}'", property));
+        match &self.block {
+            Block::None => return Err(format!("'{}' outside of any block", property)),
+            Block::Settings => {
+                let settings = &mut self.settings;
+                match property {
+                    "width" => settings.width = parse_single(values)?,
+                    "height" => settings.height = parse_single(values)?,
+                    "spp" => settings.spp = parse_single(values)?,
+                    "max_depth" => settings.tracer.max_depth = parse_single(values)?,
+                    "sky_zenith" => settings.tracer.sky_zenith = parse_vec3(values)?,
+                    "sky_horizon" => settings.tracer.sky_horizon = parse_vec3(values)?,
+                    _ => return unknown(),
+                }
+                if settings.width == 0 || settings.height == 0 || settings.spp == 0 {
+                    return Err(format!("{} must be greater than zero", property));
+                }
+            },
+            Block::Camera => {
+                let camera = self.camera.as_mut().unwrap();
+                match property {
+                    "position" => camera.position = parse_vec3(values)?,
+                    "rotation" => camera.rotation = parse_vec3(values)?,
+                    "fov" => camera.fov = Some(parse_single(values)?),
+                    "fov_axis" => camera.fov_axis = Some(match values {
+                        ["horizontal"] => FovAxis::Horizontal,
+                        ["vertical"] => FovAxis::Vertical,
+                        _ => return Err(format!("Unknown fov axis '{}', expected horizontal or vertical", values.join(" "))),
+                    }),
+                    "orthographic" => camera.ortho_size = Some(parse_single(values)?),
+                    "aperture" => camera.aperture = Some(parse_single(values)?),
+                    "focus" => camera.focus_distance = Some(parse_single(values)?),
+                    _ => return unknown(),
+                }
+            },
+            Block::Material(name) => {
+                let (_, material) = self.materials.get_mut(name).unwrap();
+                match property {
+                    "bsdf" => material.bsdf = values.join(" "),
+                    "albedo" => material.albedo = parse_vec3(values)?,
+                    "roughness" => material.roughness = parse_single(values)?,
+                    "ior" => material.ior = parse_single(values)?,
+                    "emission" => material.emission = parse_vec3(values)?,
+                    "texture" => material.texture = Some(parse_path(values)?),
+                    "bump" => material.bump = Some(match values {
+                        [file] => (file.to_string(), 1.),
+                        [file, scale] => (file.to_string(), parse_number(scale)?),
+                        _ => return Err("Expected a file and an optional scale".to_string()),
+                    }),
+                    _ => return unknown(),
+                }
+            },
+            Block::Object(index) => {
+                let object = &mut self.objects[*index];
+                match property {
+                    "file" => object.file = Some(parse_path(values)?),
+                    "origin" => object.origin = parse_vec3(values)?,
+                    "rotation" => object.rotation = parse_vec3(values)?,
+                    "scale" => object.scale = parse_vec3(values)?,
+                    "use_material" => object.material = Some(values.join(" ")),
+                    "weld" => object.weld = true,
+                    _ => return unknown(),
+                }
+            },
+            Block::Light(index) => {
+                let light = &mut self.lights[*index];
+                match property {
+                    "position" => light.position = parse_vec3(values)?,
+                    "rotation" => light.rotation = parse_vec3(values)?,
+                    "size" => match parse_floats(values)?.as_slice() {
+                        [width, depth] => light.size = Vec2::new(*width, *depth),
+                        _ => return Err("Expected a width and a depth".to_string()),
+                    },
+                    "color" => light.color = parse_vec3(values)?,
+                    "intensity" => light.intensity = parse_single(values)?,
+                    _ => return unknown(),
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn build(self, file_path: &str, directory: &Path) -> Result<Scene, ImportError> {
+        let error = |line: usize, reason: String| ImportError::new(file_path, Some(line), reason);
+
+        let mut materials = HashMap::new();
+        for (name, (line, entry)) in &self.materials {
+            materials.insert(name.as_str(), build_material(name, *line, entry, file_path, directory)?);
+        }
+
+        let mut objects = Vec::new();
+        let mut file_camera = None;
+        for entry in &self.objects {
+            let material = match &entry.material {
+                Some(name) => {
+                    let material = materials.get(name.as_str()).cloned().ok_or_else(|| error(entry.line, format!("Unknown material '{}'", name)))?;
+                    let (line, material_entry) = &self.materials[name];
+                    Some((material, (name.clone(), *line, material_entry.clone())))
+                },
+                None => None,
+            };
+            let (entry_objects, camera) = load_object(entry, material, file_path, directory)?;
+            file_camera = file_camera.or(camera);
+            objects.extend(entry_objects);
+        }
+
+        for light in &self.lights {
+            let (x, z) = (light.size.x / 2., light.size.y / 2.);
+            let corners = [Vec3::new(-x, 0., -z), Vec3::new(x, 0., -z), Vec3::new(x, 0., z), Vec3::new(-x, 0., z)];
+            let vertex = |i: usize| Vertex::new(corners[i], Vec2::ZERO);
+            let quad = Object::new(vec![
+                Triangle { vertices: [vertex(0), vertex(2), vertex(1)], material: 0 },
+                Triangle { vertices: [vertex(0), vertex(3), vertex(2)], material: 0 },
+            ]);
+            let mut object = place(quad, euler(light.rotation), Vec3::ONE, light.position);
+            object.name = light.name.clone();
+            object.materials = vec![Material::emissive(light.color, light.intensity)];
+            objects.push(object);
+        }
+
+        if objects.is_empty() {
+            return Err(ImportError::new(file_path, None, "Scene has no objects or lights"));
+        }
+
+        // Without a camera block the first camera from a glTF file is used, if there is one
+        let camera = match self.camera {
+            Some(entry) => {
+                let mut camera = Camera::new(entry.position, entry.rotation);
+                camera.fov = entry.fov.unwrap_or(camera.fov);
+                camera.fov_axis = entry.fov_axis.unwrap_or(camera.fov_axis);
+                if let Some(ortho_size) = entry.ortho_size {
+                    camera.orthographic = true;
+                    camera.ortho_size = ortho_size;
+                }
+                camera.aperture = entry.aperture.unwrap_or(camera.aperture);
+                camera.focus_distance = entry.focus_distance.unwrap_or(camera.focus_distance);
+                camera
+            },
+            None => file_camera.unwrap_or_else(|| Camera::new(Vec3::ZERO, Vec3::ZERO)),
+        };
+
+        let mut scene = Scene::new(objects, camera, self.settings.width, self.settings.height);
+        scene.settings = self.settings;
+        Ok(scene)
+    }
+}
+
+fn build_material(name: &str, line: usize, entry: &MaterialEntry, file_path: &str, directory: &Path) -> Result<Material, ImportError> {
+    let error = |reason: String| ImportError::new(file_path, Some(line), reason);
+    let mut material = match entry.bsdf.as_str() {
+        "lambertian" => Material::lambertian(entry.albedo),
+        "metal" => Material::metal(entry.albedo, entry.roughness),
+        "dielectric" => Material::dielectric(entry.ior),
+        "emissive" => Material::emissive(Vec3::ONE, 1.),
+        bsdf => return Err(error(format!("Unknown bsdf '{}' in material '{}'", bsdf, name))),
+    };
+    material.emission = entry.emission;
+    if entry.bsdf == "dielectric" {
+        material.albedo = entry.albedo; // Tint, default glass is clear
+    }
+    let load = |texture: &str| Texture::new_from_file(&directory.join(texture).to_string_lossy()).map(Arc::new).map_err(|e| error(e.to_string()));
+    if let Some(texture) = &entry.texture {
+        material.albedo_texture = Some(load(texture)?);
+    }
+    if let Some((bump, scale)) = &entry.bump {
+        material.bump_texture = Some(load(bump)?);
+        material.bump_scale = *scale;
+    }
+    Ok(material)
+}
+
+// Imports and places the objects of one object block. The material override is passed built and as its entry,
+// so the reloader can read its textures again too
+type MaterialOverride = (Material, (String, usize, MaterialEntry));
+
+fn load_object(entry: &ObjectEntry, material: Option<MaterialOverride>, file_path: &str, directory: &Path) -> Result<(Vec<Object>, Option<Camera>), ImportError> {
+    let error = |reason: String| ImportError::new(file_path, Some(entry.line), reason);
+    let file = entry.file.as_ref().ok_or_else(|| error(format!("Object '{}' has no file", entry.name)))?;
+    let imported = Importer::file(&directory.join(file).to_string_lossy(), false, entry.weld).map_err(|e| error(e.to_string()))?;
+
+    let reloader = {
+        let (entry, file_path, directory) = (entry.clone(), file_path.to_string(), directory.to_path_buf());
+        let material_entry = material.as_ref().map(|(_, material_entry)| material_entry.clone());
+        Reloader::new(move || {
+            let material = match &material_entry {
+                Some((name, line, material_entry)) => Some((build_material(name, *line, material_entry, &file_path, &directory)?, (name.clone(), *line, material_entry.clone()))),
+                None => None,
+            };
+            load_object(&entry, material, &file_path, &directory).map(|(objects, _)| objects)
+        })
+    };
+
+    let single = imported.objects.len() == 1;
+    let objects = imported.objects.into_iter().map(|object| {
+        let mut object = place(object, euler(entry.rotation), entry.scale, entry.origin);
+        object.name = if single { entry.name.clone() } else { format!("{}/{}", entry.name, object.name) };
+        if let Some((material, _)) = &material {
+            // Every slot is replaced so triangle material indices stay valid
+            object.materials = vec![material.clone(); object.materials.len()];
+        }
+        object.reloader = Some(reloader.clone());
+        object
+    }).collect();
+    Ok((objects, imported.camera))
+}
+
+// Yaw, pitch and roll in degrees, applied the same way the camera applies them
+fn euler(rotation: Vec3) -> Quat {
+    Quat::from_euler(EulerRot::YXZ, rotation.x.to_radians(), rotation.y.to_radians(), rotation.z.to_radians())
+}
+
+// Rotation and scale are baked into the vertices around the object's origin, the origin itself is transformed too.
+// The triangles are edited in place and the importer's BVH is refitted, so cached or freshly built trees are reused
+fn place(mut object: Object, rotation: Quat, scale: Vec3, translation: Vec3) -> Object {
+    let linear = Mat3::from_quat(rotation) * Mat3::from_diagonal(scale);
+    if linear != Mat3::IDENTITY {
+        let normal_matrix = linear.inverse().transpose();
+        for triangle in &object.triangles {
+            let mut triangle = triangle.write().unwrap();
+            for vertex in &mut triangle.vertices {
+                vertex.pos = linear * vertex.pos;
+                vertex.normal = vertex.normal.map(|normal| (normal_matrix * normal).normalize_or_zero());
+            }
+        }
+        object.refit();
+    }
+    object.origin = linear * object.origin + translation;
+    object
+}
+
+fn parse_single<T: std::str::FromStr>(values: &[&str]) -> Result<T, String> {
+    match values {
+        [value] => parse_number(value),
+        _ => Err(format!("Expected one value, got {}", values.len())),
+    }
+}
+
+// One value for all three components or three values
+fn parse_vec3(values: &[&str]) -> Result<Vec3, String> {
+    match parse_floats(values)?.as_slice() {
+        [value] => Ok(Vec3::splat(*value)),
+        [x, y, z] => Ok(Vec3::new(*x, *y, *z)),
+        _ => Err(format!("Expected 1 or 3 values, got {}", values.len())),
+    }
+}
+
+// File names may contain spaces
+fn parse_path(values: &[&str]) -> Result<String, String> {
+    match values.join(" ") {
+        path if path.is_empty() => Err("Missing file name".to_string()),
+        path => Ok(path),
+    }
+}
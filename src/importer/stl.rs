@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use glam::{Vec2, Vec3};
+
+use crate::object::Object;
+use crate::polygon::{ Vertex, Triangle };
+use super::{ Importer, ImportError, file_stem, parse_floats };
+
+const HEADER_SIZE: usize = 84; // 80 byte header and the triangle count
+const FACET_SIZE: usize = 50; // Normal, 3 vertices and a 2 byte attribute
+// Welded positions are compared after snapping to this grid, CAD exports often differ in the last bits
+const WELD_TOLERANCE: f32 = 1e-5;
+const CREASE_ANGLE: f32 = 60.; // Degrees, sharper edges between facets stay hard when welding
+
+impl Importer {
+    // Detects ASCII or binary STL. Welding merges vertices at the same position and gives them smooth normals,
+    // without it every facet is flat like in the file
+    pub fn stl(file_path: &str, weld: bool) -> Result<Object, ImportError> {
+        let bytes = std::fs::read(file_path).map_err(|e| ImportError::new(file_path, None, format!("Cannot open file: {}", e)))?;
+
+        let (name, mut triangles) = if is_binary(&bytes) {
+            (None, parse_binary(&bytes).map_err(|reason| ImportError::new(file_path, None, reason))?)
+        } else {
+            parse_ascii(&String::from_utf8_lossy(&bytes)).map_err(|(line, reason)| ImportError::new(file_path, Some(line), reason))?
+        };
+        // Degenerate facets can't be hit and would only make the BVH bigger
+        triangles.retain(|triangle| facet_normal(triangle) != Vec3::ZERO);
+        if triangles.is_empty() {
+            return Err(ImportError::new(file_path, None, "File has no facets"));
+        }
+        if weld {
+            weld_vertices(&mut triangles);
+        }
+
+        let mut object = Object::new(triangles);
+        object.name = name.unwrap_or_else(|| file_stem(file_path));
+        object.files = vec![file_path.to_string()];
+        Ok(object)
+    }
+}
+
+// Binary files may start with "solid" too, so the size implied by the triangle count is checked first
+fn is_binary(bytes: &[u8]) -> bool {
+    if let Some(count) = bytes.get(80..HEADER_SIZE) {
+        let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+        if bytes.len() == HEADER_SIZE + count * FACET_SIZE {
+            return true;
+        }
+    }
+    !bytes.trim_ascii_start().starts_with(b"solid")
+}
+
+fn parse_binary(bytes: &[u8]) -> Result<Vec<Triangle>, String> {
+    let Some(count) = bytes.get(80..HEADER_SIZE) else {
+        return Err("File is too short for a binary STL header".to_string());
+    };
+    let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+    let facets = &bytes[HEADER_SIZE..];
+    if facets.len() < count * FACET_SIZE {
+        return Err(format!("Header lists {} facets but the file only has data for {}", count, facets.len() / FACET_SIZE));
+    }
+
+    let read_vec3 = |data: &[u8]| {
+        let float = |i: usize| f32::from_le_bytes([data[i], data[i+1], data[i+2], data[i+3]]);
+        Vec3::new(float(0), float(4), float(8))
+    };
+    // Stored normals are skipped, they are often missing or wrong and the winding defines the facet anyway
+    Ok(facets.chunks_exact(FACET_SIZE).take(count).map(|facet| facet_triangle([
+        read_vec3(&facet[12..24]),
+        read_vec3(&facet[24..36]),
+        read_vec3(&facet[36..48]),
+    ])).collect())
+}
+
+// Returns the solid name and its facets, errors carry a 1 based line number
+fn parse_ascii(text: &str) -> Result<(Option<String>, Vec<Triangle>), (usize, String)> {
+    let mut name = None;
+    let mut triangles = Vec::new();
+    let mut corners = Vec::with_capacity(3);
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first().copied() {
+            None => {},
+            Some("solid") => name = Some(words[1..].join(" ")).filter(|name| !name.is_empty()),
+            Some("facet") => corners.clear(),
+            Some("vertex") => {
+                let values = parse_floats(&words[1..]).map_err(|reason| (line_number, reason))?;
+                if values.len() != 3 {
+                    return Err((line_number, format!("Vertex with {} components, expected 3", values.len())));
+                }
+                if corners.len() == 3 {
+                    return Err((line_number, "Facet with more than 3 vertices".to_string()));
+                }
+                corners.push(Vec3::new(values[0], values[1], values[2]));
+            },
+            Some("endfacet") => {
+                if corners.len() != 3 {
+                    return Err((line_number, format!("Facet with {} vertices, expected 3", corners.len())));
+                }
+                triangles.push(facet_triangle([corners[0], corners[1], corners[2]]));
+            },
+            Some("outer") | Some("endloop") | Some("endsolid") => {},
+            Some(keyword) => return Err((line_number, format!("Unknown keyword '{}'", keyword))),
+        }
+    }
+    Ok((name, triangles))
+}
+
+fn facet_triangle(corners: [Vec3; 3]) -> Triangle {
+    Triangle { vertices: corners.map(|corner| Vertex::new(corner, Vec2::ZERO)), material: 0 }
+}
+
+// Area weighted, so larger facets have more say in the welded normal
+fn facet_normal(triangle: &Triangle) -> Vec3 {
+    let [a, b, c] = &triangle.vertices;
+    (b.pos - a.pos).cross(c.pos - a.pos)
+}
+
+fn weld_vertices(triangles: &mut [Triangle]) {
+    // 64 bit keys, 32 bit ones would saturate for coordinates past 21474 units
+    let key = |position: Vec3| (position / WELD_TOLERANCE).round().as_i64vec3().to_array();
+    // Position of the first vertex and the normals of every facet sharing it
+    let mut welded: HashMap<[i64; 3], (Vec3, Vec<Vec3>)> = HashMap::new();
+    for triangle in triangles.iter() {
+        let normal = facet_normal(triangle);
+        for vertex in &triangle.vertices {
+            welded.entry(key(vertex.pos)).or_insert((vertex.pos, Vec::new())).1.push(normal);
+        }
+    }
+    let min_cos = CREASE_ANGLE.to_radians().cos();
+    for triangle in triangles.iter_mut() {
+        let own = facet_normal(triangle).normalize();
+        for vertex in &mut triangle.vertices {
+            let (position, normals) = &welded[&key(vertex.pos)];
+            vertex.pos = *position;
+            // Facets meeting at a sharp edge keep their own normal there
+            let smooth: Vec3 = normals.iter().filter(|normal| normal.normalize().dot(own) >= min_cos).sum();
+            vertex.normal = smooth.try_normalize();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weld_merges_close_vertices() {
+        let mut triangles = vec![
+            facet_triangle([Vec3::ZERO, Vec3::X, Vec3::Y]),
+            facet_triangle([Vec3::X + Vec3::splat(WELD_TOLERANCE * 0.1), Vec3::new(1., 1., 0.), Vec3::Y]),
+        ];
+        weld_vertices(&mut triangles);
+        assert_eq!(triangles[1].vertices[0].pos, Vec3::X);
+        assert!(triangles.iter().flat_map(|triangle| &triangle.vertices).all(|vertex| vertex.normal.is_some_and(|normal| normal.abs_diff_eq(Vec3::Z, 1e-4))));
+    }
+
+    #[test]
+    fn weld_keeps_large_coordinates_apart() {
+        // Millimeter models of a few dozen meters, 32 bit keys made all of these the same vertex
+        let corners = [Vec3::new(30000., 0., 0.), Vec3::new(50000., 0., 0.), Vec3::new(30000., 30000., 0.)];
+        let mut triangles = vec![facet_triangle(corners)];
+        weld_vertices(&mut triangles);
+        let welded: Vec<Vec3> = triangles[0].vertices.iter().map(|vertex| vertex.pos).collect();
+        assert_eq!(welded, corners);
+        assert_ne!(facet_normal(&triangles[0]), Vec3::ZERO);
+    }
+}
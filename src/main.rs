@@ -2,28 +2,73 @@
 #![allow(non_snake_case)]
 #![windows_subsystem = "windows"]
 
-use minifb::{Key, Window, WindowOptions};
+use std::sync::Arc;
 
-use glam::{Vec2, Vec3};
+use minifb::{Key, MouseButton, MouseMode, Window, WindowOptions};
 
-pub const WIDTH:  usize = 512;
-pub const HEIGHT: usize = 288;
+use glam::{Vec2, Vec3};
 
-pub const ASPECT_RATIO: f32 = WIDTH as f32 / HEIGHT as f32;
+// Initial resolution, the window can be resized afterwards
+pub const DEFAULT_WIDTH:  usize = 512;
+pub const DEFAULT_HEIGHT: usize = 288;
+// Has to match WindowOptions::scale, window size is divided by it to get render resolution
+const WINDOW_SCALE: usize = 2;
+const SCREENSHOT_SAMPLES: u32 = 64;
 
 mod importer;
 use importer::Importer;
 
+mod exporter;
+
+mod cli;
+
 mod helper;
 use crate::texture::Texture;
 use crate::helper::*;
-use crate::object::Object;
-use crate::screen::ScreenBuffers;
+use crate::object::{ Object, Reloader };
 use crate::polygon::{ Triangle, Vertex };
-use crate::camera::Camera;
+use crate::camera::{ Camera, FovAxis };
 use crate::scene::Scene;
+use crate::material::Material;
+use crate::watcher::Watcher;
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("render") {
+        let render_args = cli::RenderArgs::parse(&args[1..]).unwrap_or_else(|e| {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        });
+        if let Err(e) = cli::render(&render_args) {
+            eprintln!("Render failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.first().map(String::as_str) == Some("convert") {
+        let convert_args = cli::ConvertArgs::parse(&args[1..]).unwrap_or_else(|e| {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        });
+        if let Err(e) = cli::convert(&convert_args) {
+            eprintln!("Convert failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // A scene file can be passed instead of using the built in demo
+    let mut scene = match args.first() {
+        Some(path) => Scene::load(path).unwrap_or_else(|e| {
+            eprintln!("Cannot load scene: {}", e);
+            std::process::exit(1);
+        }),
+        None => demo_scene(),
+    };
+    let mut tracer = scene.settings.tracer.clone();
+    // Meshes, textures and the scene file are reloaded when they change on disk
+    let mut watcher = Watcher::new(&scene);
+
     let options = WindowOptions {
         borderless: false,
         title: true,
@@ -36,8 +81,8 @@ fn main() {
     };
     let mut window = Window::new(
         "RT",
-        WIDTH,
-        HEIGHT,
+        scene.settings.width,
+        scene.settings.height,
         options,
     ).unwrap_or_else( |e| {
         panic!("{}", e);
@@ -46,52 +91,27 @@ fn main() {
     // Limit to max ~60 fps update rate
     // window.set_target_fps(60);
 
-    let screen = ScreenBuffers::new(WIDTH, HEIGHT);
-
-    let tri = Triangle{
-        vertices: [
-            Vertex{pos: Vec3::new(0.0, 1.0, 0.0),  uv: Vec2::new(0.0, 1.0)},
-            Vertex{pos: Vec3::new(-1.0, 0.0, 0.0), uv: Vec2::new(-1.0, 0.0)},
-            Vertex{pos: Vec3::new(0.0, 0.0, 0.0),  uv: Vec2::new(0.0, 0.0)}
-        ]
-    };
-    let reference_tri = Triangle{vertices: [Vertex{pos: Vec3::new(0.0, 100.0, 0.0), uv: Vec2::new(0.0, 100.0)}, Vertex{pos: Vec3::new(0.0, 0.0, 0.0), uv: Vec2::new(0.0, 0.0)}, Vertex{pos: Vec3::new(100.0, 0.0, 0.0), uv: Vec2::new(100.0, 0.0)}]};
-
-    let mut ref_obj = Object::new(vec![reference_tri]);
-    ref_obj.origin = Vec3::new(0., 0., -100.);
-    
-    let obj = Object::new(vec![tri]);
-
-    let cube = Importer::obj("test/cube.obj");
-
-    let mut teapot = Importer::obj("test/teapot_6320tri.obj");
-    teapot.origin = Vec3::new(10., 0., 0.);
-    println!("Total BVH leaves: {}", teapot.debug_get_bvh_end(teapot.bvh.clone()).len());
-    teapot.debug_count_repeated_triangles();
-    // let tris22 = cube.triangles.iter().collect();
-    // BVH::generate_bottom(&tris22);
-
-    let mut scene = Scene {
-        screen: screen,
-        objects: vec![
-            obj,
-            ref_obj,
-            cube,
-            teapot
-        ],
-        camera: {Camera::new(Vec3::new(0.0, 1.0, 2.0), Vec3::new(-80., 0., 0.))},
-    };
 
+    let mut frame_index = 0;
     let mut frames_rendered = 0;
     let mut now = std::time::Instant::now();
     let mut show_depth_buffer = false;
-    let temp_cam = Camera::new(Vec3::new(0.0, 0.0, 2.0), Vec3::ZERO);
-    let temp_tex = Texture::new(WIDTH, HEIGHT);
+    let mut mouse_was_down = false;
+    let mut selected_object = 0;
     while window.is_open() && !window.is_key_down(Key::Escape) {
+        let (window_width, window_height) = window.get_size();
+        let (width, height) = ((window_width / WINDOW_SCALE).max(1), (window_height / WINDOW_SCALE).max(1));
+        if (width, height) != (scene.screen.width(), scene.screen.height()) {
+            scene.resize(width, height);
+        }
 
-        scene.screen.clear();
+        if watcher.poll(&mut scene) {
+            tracer = scene.settings.tracer.clone();
+            selected_object = selected_object.min(scene.objects.len() - 1);
+        }
 
-        scene.camera.render(&scene.objects, &mut scene.screen);
+        scene.render_frame(&tracer, frame_index);
+        frame_index += 1;
 
         // Controls
             let speed_multiplier;
@@ -112,36 +132,139 @@ fn main() {
             if window.is_key_down(Key::RightAlt)   { scene.camera.rotate(Vec3::new( 0.0,  0.0,  0.2) * speed_multiplier) }
             if window.is_key_down(Key::RightShift) { scene.camera.rotate(Vec3::new( 0.0,  0.0, -0.2) * speed_multiplier) }
 
-            // Cube controls
-            if window.is_key_down(Key::I)          { scene.objects[0].origin += scene.camera.front().with_y(0.).normalize()*0.02 * speed_multiplier }
-            if window.is_key_down(Key::K)          { scene.objects[0].origin += scene.camera.back() .with_y(0.).normalize()*0.02 * speed_multiplier }
-            if window.is_key_down(Key::L)          { scene.objects[0].origin += scene.camera.right().with_y(0.).normalize()*0.02 * speed_multiplier }
-            if window.is_key_down(Key::J)          { scene.objects[0].origin += scene.camera.left() .with_y(0.).normalize()*0.02 * speed_multiplier }
-            if window.is_key_down(Key::O)          { scene.objects[0].origin += Vec3::new( 0.00,  0.02,  0.00) * speed_multiplier }
-            if window.is_key_down(Key::U)          { scene.objects[0].origin += Vec3::new( 0.00, -0.02,  0.00) * speed_multiplier }
+            if window.is_key_down(Key::Equal)      { scene.camera.zoom(1. / (1. + 0.01 * speed_multiplier)) }
+            if window.is_key_down(Key::Minus)      { scene.camera.zoom(1. + 0.01 * speed_multiplier) }
+            if window.is_key_down(Key::RightBracket) { scene.camera.aperture += 0.002 * speed_multiplier }
+            if window.is_key_down(Key::LeftBracket)  { scene.camera.aperture = (scene.camera.aperture - 0.002 * speed_multiplier).max(0.) }
+            if window.is_key_pressed(Key::V, minifb::KeyRepeat::No) {
+                scene.camera.fov_axis = match scene.camera.fov_axis { FovAxis::Horizontal => FovAxis::Vertical, FovAxis::Vertical => FovAxis::Horizontal };
+                println!("FOV is now {:?}", scene.camera.fov_axis);
+            }
+
+            // Object controls, Tab selects the next object
+            if window.is_key_pressed(Key::Tab, minifb::KeyRepeat::No) {
+                selected_object = (selected_object + 1) % scene.objects.len();
+                println!("Selected object {} '{}'", selected_object, scene.objects[selected_object].name);
+            }
+            let selected = &mut scene.objects[selected_object];
+            if window.is_key_down(Key::I)          { selected.origin += scene.camera.front().with_y(0.).normalize()*0.02 * speed_multiplier }
+            if window.is_key_down(Key::K)          { selected.origin += scene.camera.back() .with_y(0.).normalize()*0.02 * speed_multiplier }
+            if window.is_key_down(Key::L)          { selected.origin += scene.camera.right().with_y(0.).normalize()*0.02 * speed_multiplier }
+            if window.is_key_down(Key::J)          { selected.origin += scene.camera.left() .with_y(0.).normalize()*0.02 * speed_multiplier }
+            if window.is_key_down(Key::O)          { selected.origin += Vec3::new( 0.00,  0.02,  0.00) * speed_multiplier }
+            if window.is_key_down(Key::U)          { selected.origin += Vec3::new( 0.00, -0.02,  0.00) * speed_multiplier }
             
             // Misc controls
-            if window.is_key_pressed(Key::P, minifb::KeyRepeat::No) { scene.camera.orthographic = !scene.camera.orthographic }
+            if window.is_key_pressed(Key::P, minifb::KeyRepeat::No) { scene.camera.toggle_orthographic() }
             if window.is_key_pressed(Key::B, minifb::KeyRepeat::No) { show_depth_buffer = !show_depth_buffer }
-            if window.is_key_pressed(Key::R, minifb::KeyRepeat::No) { scene.render_to_texture(&temp_cam, &temp_tex); temp_tex.save_to_file(); }
+            // Click to focus, F focuses on the center of the screen
+            let mouse_down = window.get_mouse_down(MouseButton::Left);
+            let focus_pixel = if mouse_down && !mouse_was_down {
+                window.get_mouse_pos(MouseMode::Discard).map(|(x, y)| (x as usize, y as usize))
+            } else if window.is_key_pressed(Key::F, minifb::KeyRepeat::No) {
+                Some((scene.screen.width() / 2, scene.screen.height() / 2))
+            } else {
+                None
+            };
+            mouse_was_down = mouse_down;
+            if let Some(depth) = focus_pixel.and_then(|(x, y)| scene.camera.depth_at(&scene.screen, x, y)) {
+                scene.camera.focus_distance = depth;
+                println!("Focused at {:.3}", depth);
+            }
+            if window.is_key_pressed(Key::R, minifb::KeyRepeat::No) {
+                // Screenshot at full window resolution
+                let screenshot = Texture::new(scene.screen.width() * WINDOW_SCALE, scene.screen.height() * WINDOW_SCALE);
+                scene.render_to_texture(&scene.camera, &screenshot, &tracer, SCREENSHOT_SAMPLES);
+                screenshot.save_to_file();
+            }
 
         // We unwrap here as we want this code to exit if it fails. Real applications may want to handle this in a different way
         if !show_depth_buffer {
             window
-                .update_with_buffer(&scene.screen.get_rendered(), WIDTH, HEIGHT)
+                .update_with_buffer(&scene.screen.get_rendered(), scene.screen.width(), scene.screen.height())
                 .unwrap();
         } else {
             window
-                .update_with_buffer(&scene.screen.get_depth(), WIDTH, HEIGHT)
+                .update_with_buffer(&scene.screen.get_depth(), scene.screen.width(), scene.screen.height())
                 .unwrap();
         }
 
         frames_rendered += 1;
         if now.elapsed().as_millis() >= 1000 {
             let elapsed = now.elapsed();
-            println!("{} fps,\t{} millis per frame", frames_rendered as f64/elapsed.as_secs_f64(), elapsed.as_millis() as f64/frames_rendered as f64);
+            println!("{} fps,\t{} millis per frame,\t{} frames accumulated,\tFOV {:.1},\tortho size {:.2},\taperture {:.3}", frames_rendered as f64/elapsed.as_secs_f64(), elapsed.as_millis() as f64/frames_rendered as f64, scene.accumulation.frames(), scene.camera.fov, scene.camera.ortho_size, scene.camera.aperture);
             frames_rendered = 0;
             now = std::time::Instant::now();
         }
     };
 }
+
+// Hot reload runs setup again, so reloaded meshes keep their demo materials and placement
+fn demo_mesh(path: &'static str, setup: fn(&mut Object)) -> Option<Object> {
+    let load = move || Importer::obj(path).map(|mut object| {
+        setup(&mut object);
+        vec![object]
+    });
+    match load() {
+        Ok(mut objects) => {
+            let mut object = objects.remove(0);
+            object.reloader = Some(Reloader::new(load));
+            Some(object)
+        },
+        Err(e) => {
+            eprintln!("Skipping {}: {}", path, e);
+            None
+        },
+    }
+}
+
+// Textured reference plane, rebuilt when its texture changes
+fn checker_object() -> Object {
+    let reference_tri = Triangle{vertices: [Vertex::new(Vec3::new(0.0, 100.0, 0.0), Vec2::new(0.0, 100.0)), Vertex::new(Vec3::new(0.0, 0.0, 0.0), Vec2::new(0.0, 0.0)), Vertex::new(Vec3::new(100.0, 0.0, 0.0), Vec2::new(100.0, 0.0))], material: 0};
+
+    let mut ref_obj = Object::new(vec![reference_tri]);
+    ref_obj.name = "checker".to_string();
+    ref_obj.origin = Vec3::new(0., 0., -100.);
+    let mut checker = Material::lambertian(Vec3::ONE);
+    match Texture::new_from_file("test/test_image.ppm") {
+        Ok(texture) => checker.albedo_texture = Some(Arc::new(texture)),
+        Err(e) => eprintln!("Skipping checker texture: {}", e),
+    }
+    ref_obj.materials = vec![checker];
+    ref_obj
+}
+
+fn demo_scene() -> Scene {
+    let tri = Triangle{
+        vertices: [
+            Vertex::new(Vec3::new(0.0, 1.0, 0.0),  Vec2::new(0.0, 1.0)),
+            Vertex::new(Vec3::new(-1.0, 0.0, 0.0), Vec2::new(-1.0, 0.0)),
+            Vertex::new(Vec3::new(0.0, 0.0, 0.0),  Vec2::new(0.0, 0.0))
+        ],
+        material: 0,
+    };
+    let mut ref_obj = checker_object();
+    ref_obj.reloader = Some(Reloader::new(|| Ok(vec![checker_object()])));
+
+    let mut obj = Object::new(vec![tri]);
+    obj.name = "light".to_string();
+    obj.materials = vec![Material::emissive(Vec3::new(1.0, 0.9, 0.7), 5.)];
+
+    let mut objects = vec![obj, ref_obj];
+
+    // Missing or broken demo meshes are skipped, the rest of the scene still works
+    objects.extend(demo_mesh("test/cube.obj", |cube| {
+        cube.materials = vec![Material::dielectric(1.5)];
+    }));
+    objects.extend(demo_mesh("test/teapot_6320tri.obj", |teapot| {
+        teapot.origin = Vec3::new(10., 0., 0.);
+        teapot.materials = vec![Material::metal(Vec3::new(0.9, 0.7, 0.4), 0.2)];
+    }));
+
+    Scene::new(
+        objects,
+        Camera::new(Vec3::new(0.0, 1.0, 2.0), Vec3::new(-80., 0., 0.)),
+        DEFAULT_WIDTH,
+        DEFAULT_HEIGHT,
+    )
+}